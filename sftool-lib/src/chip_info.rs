@@ -0,0 +1,379 @@
+use crate::ram_command::{Command, RamCommand, parse_hex_output};
+use crate::write_flash::CHIP_MEMORY_LAYOUT;
+use crate::{SifliTool, SifliToolBase, attempt_connect, hex, ports};
+use indicatif::ProgressStyle;
+use phf::phf_map;
+use probe_rs::{MemoryInterface, Session};
+use std::fmt;
+use std::time::Duration;
+
+// HPSYS_CFG->IDR 寄存器地址，REVID[7:0] PID[15:8] CID[23:16] SID[31:24]
+pub(crate) static CHIP_ID_REGISTER: phf::Map<&'static str, u64> = phf_map! {
+    "sf32lb52" => 0x5000_B004,
+};
+
+pub trait ChipInfoTrait {
+    fn chip_info(&mut self) -> Result<ChipInfo, std::io::Error>;
+}
+
+// stub 对 NAND 按 128KB 的块擦除，NOR 为 4KB 的整数倍，SD 卡为 512 字节的扇区
+const NAND_BLOCK_SIZE: u32 = 128 * 1024;
+const SD_SECTOR_SIZE: u32 = 512;
+
+/// stub 在 `burn_read` 等命令中查找 flash 时输出的信息：
+/// `addr:0x%x, size:0x%x sector:0x%x page:0x%x id:0x%x`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashInfo {
+    pub address: u32,
+    pub size: u64,
+    /// 擦除单位
+    pub sector_size: u32,
+    pub page_size: u32,
+    /// flash 驱动读到的 ID，按 stub 输出的数值原样保存
+    pub jedec_id: u32,
+}
+
+impl FlashInfo {
+    /// 解析 stub 输出中的 flash 信息行，地址上没有 flash 时 stub 输出的大小为 0，返回 `None`
+    pub(crate) fn parse(output: &str) -> Option<Self> {
+        let line = &output[output.find("addr:")?..];
+        let line = line.lines().next()?;
+        let field = |name: &str| -> Option<u32> {
+            let value = line.split(name).nth(1)?.strip_prefix("0x")?;
+            let hex: String = value.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+            u32::from_str_radix(&hex, 16).ok()
+        };
+        let info = FlashInfo {
+            address: field("addr:")?,
+            size: field("size:")? as u64,
+            sector_size: field("sector:")?,
+            page_size: field("page:")?,
+            jedec_id: field("id:")?,
+        };
+        if info.size == 0 || info.sector_size == 0 {
+            return None;
+        }
+        Some(info)
+    }
+
+    pub fn is_nand(&self) -> bool {
+        self.sector_size == NAND_BLOCK_SIZE
+    }
+
+    /// 根据擦除单位判断的存储类型
+    pub fn memory_type(&self) -> &'static str {
+        match self.sector_size {
+            NAND_BLOCK_SIZE => "nand",
+            SD_SECTOR_SIZE => "sd",
+            _ => "nor",
+        }
+    }
+}
+
+fn format_size(size: u64) -> String {
+    if size >= 1024 * 1024 {
+        format!("{} MB", size / (1024 * 1024))
+    } else {
+        format!("{} KB", size / 1024)
+    }
+}
+
+impl fmt::Display for FlashInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08X} {}, ID 0x{:06X}, {}, {} KB sectors, {} B pages",
+            self.address,
+            self.memory_type().to_uppercase(),
+            self.jedec_id,
+            format_size(self.size),
+            self.sector_size / 1024,
+            self.page_size
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChipInfo {
+    pub chip: String,
+    pub memory_type: String,
+    /// HPSYS_CFG->IDR 的原始值
+    pub chip_id: Option<u32>,
+    pub uid: Option<Vec<u8>>,
+    pub flash: Vec<FlashInfo>,
+}
+
+impl ChipInfo {
+    pub fn revision(&self) -> Option<u8> {
+        self.chip_id.map(|id| (id & 0xFF) as u8)
+    }
+
+    pub fn package_id(&self) -> Option<u8> {
+        self.chip_id.map(|id| ((id >> 8) & 0xFF) as u8)
+    }
+
+    /// 检测到的存储类型是否与 `--memory` 一致，没有检测到 flash 时返回 `None`。
+    /// stub 无法检测 SD 卡，`--memory sd` 时同样返回 `None`
+    pub fn memory_matches(&self) -> Option<bool> {
+        if self.flash.is_empty() || self.memory_type == "sd" {
+            return None;
+        }
+        // 板上可能同时有启动用的 NOR 和存放数据的 NAND/SD，只要有一个匹配即可
+        Some(
+            self.flash
+                .iter()
+                .any(|flash| flash.memory_type() == self.memory_type),
+        )
+    }
+}
+
+impl fmt::Display for ChipInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Chip:      {}", self.chip.to_uppercase())?;
+        match self.chip_id {
+            Some(id) => writeln!(
+                f,
+                "Chip ID:   0x{:08X} (revision 0x{:02X}, package 0x{:02X})",
+                id,
+                id & 0xFF,
+                (id >> 8) & 0xFF
+            )?,
+            None => writeln!(f, "Chip ID:   unknown")?,
+        }
+        match &self.uid {
+            Some(uid) => {
+                let uid = hex(uid);
+                writeln!(f, "UID:       {}", uid)?
+            }
+            None => writeln!(f, "UID:       unknown")?,
+        }
+        if self.flash.is_empty() {
+            writeln!(f, "Flash:     not detected")?;
+        }
+        for flash in self.flash.iter() {
+            writeln!(f, "Flash:     {}", flash)?;
+        }
+        match self.memory_matches() {
+            Some(true) => write!(f, "Memory:    {} (matches)", self.memory_type),
+            Some(false) => write!(
+                f,
+                "Memory:    {} (MISMATCH, check --memory)",
+                self.memory_type
+            ),
+            None => write!(f, "Memory:    {} (unable to verify)", self.memory_type),
+        }
+    }
+}
+
+// 解析 `efuse_uid_read` 在 "read uid from efuse:" 和 "EFUSE_UID_READ_PASS" 之间输出的十六进制字节
+fn parse_uid(output: &str) -> Option<Vec<u8>> {
+    parse_hex_output(output, "efuse:", "EFUSE_UID_READ_PASS")
+}
+
+/// 省略 `--chip` 时以唯一受支持的芯片连接，成功后将 `base_param.chip` 更新为该芯片名称。
+///
+/// 这不是芯片识别：ID 寄存器中没有已知的、能区分不同型号的取值，所以不按 ID 查表，
+/// 只确认 ID 寄存器有有效的值。受支持的芯片不止一个时要求指定 `--chip`。
+pub(crate) fn connect_default_chip(
+    base_param: &mut SifliToolBase,
+    step: &mut i32,
+) -> Result<Session, std::io::Error> {
+    let specify_chip = |reason: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{}, please specify --chip", reason),
+        )
+    };
+    let mut chips = CHIP_ID_REGISTER.entries();
+    let (Some((chip, address)), None) = (chips.next(), chips.next()) else {
+        return Err(specify_chip(
+            "The chip type cannot be told apart by its ID register".to_string(),
+        ));
+    };
+
+    let probe = ports::open_probe(&base_param.port_name)?;
+    let mut candidate = base_param.clone();
+    candidate.chip = chip.to_string();
+    let mut session = attempt_connect(probe, &candidate, step)
+        .map_err(|e| specify_chip(format!("Failed to connect as {}: {}", chip.to_uppercase(), e)))?;
+    let id = session
+        .core(0)
+        .and_then(|mut core| core.read_word_32(*address));
+    match id {
+        Ok(id) if id != 0 && id != u32::MAX => {
+            base_param.chip = chip.to_string();
+            if !base_param.quiet {
+                let spinner = base_param.new_spinner();
+                spinner.set_style(ProgressStyle::with_template("[{prefix}] {msg}").unwrap());
+                spinner.set_prefix(format!("0x{:02X}", step));
+                *step = step.wrapping_add(1);
+                spinner.finish_with_message(format!(
+                    "Chip: {} (the only supported chip, ID 0x{:08X})",
+                    chip.to_uppercase(),
+                    id
+                ));
+            }
+            Ok(session)
+        }
+        Ok(id) => Err(specify_chip(format!("Invalid chip ID 0x{:08X}", id))),
+        Err(e) => Err(specify_chip(format!("Failed to read the chip ID: {}", e))),
+    }
+}
+
+impl SifliTool {
+    /// 使用当前的 stub 查询 flash，返回检测到的存储类型（"nor" 或 "nand"）。
+    /// 这里不检测 SD 卡：所有地址上都没有找到 flash 时返回错误，要求指定 `--memory`。
+    pub(crate) fn detect_memory_type(&mut self) -> Result<String, std::io::Error> {
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+            spinner.set_message("Detecting memory type...");
+        }
+
+        let addresses = CHIP_MEMORY_LAYOUT
+            .get(self.base.chip.as_str())
+            .cloned()
+            .unwrap_or_default();
+        let mut found = Vec::new();
+        for address in addresses {
+            if let Some(info) = self.read_flash_info(address)? {
+                found.push(info);
+            }
+        }
+        // 板上同时有启动用的 NOR 和 NAND 时，数据存放在 NAND 上
+        let Some(memory_type) = found
+            .iter()
+            .map(|info| info.memory_type())
+            .max_by_key(|memory_type| *memory_type == "nand")
+        else {
+            if !self.base.quiet {
+                spinner.finish_with_message("No flash found");
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Unable to detect the memory type, please specify --memory",
+            ));
+        };
+
+        if !self.base.quiet {
+            spinner.finish_with_message(format!("Detected memory: {}", memory_type));
+        }
+        Ok(memory_type.to_string())
+    }
+
+    /// 读取 `address` 上 flash 的信息，没有接 flash 时返回 `None`。
+    ///
+    /// stub 没有单独查询 flash 的命令，`burn_read` 在传输数据之前会输出 flash 信息，
+    /// 这里读取 0 字节只取这一行。
+    pub(crate) fn read_flash_info(&mut self, address: u32) -> Result<Option<FlashInfo>, std::io::Error> {
+        let output = self.command_output(Command::Read { address, len: 0 }, &["OK", "Fail"])?;
+        if output.contains("Fail") {
+            return Ok(None);
+        }
+        Ok(FlashInfo::parse(&output))
+    }
+}
+
+impl ChipInfoTrait for SifliTool {
+    fn chip_info(&mut self) -> Result<ChipInfo, std::io::Error> {
+        let uid = match self.command_output(
+            Command::EfuseUidRead,
+            &["EFUSE_UID_READ_PASS", "EFUSE_READ_FAIL"],
+        ) {
+            Ok(output) => parse_uid(&output),
+            Err(e) => {
+                tracing::warn!("Failed to read UID: {}", e);
+                None
+            }
+        };
+
+        let mut flash = Vec::new();
+        if let Some(addresses) = CHIP_MEMORY_LAYOUT.get(self.base.chip.as_str()) {
+            for address in addresses.iter() {
+                if let Some(info) = self.read_flash_info(*address)? {
+                    flash.push(info);
+                }
+            }
+        }
+
+        Ok(ChipInfo {
+            chip: self.base.chip.clone(),
+            memory_type: self.base.memory_type.clone(),
+            chip_id: self.chip_id,
+            uid,
+            flash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `burn_read 0x12000000 0x0` 的输出，flash 信息行由 stub 中的
+    // "addr:0x%x, size:0x%x sector:0x%x page:0x%x id:0x%x\n" 打印
+    const NOR_OUTPUT: &str = "burn_read 0x12000000 0x00000000\r\n\
+        RT_DEVICE_FLAG_STREAM\r\n\
+        addr:0x12000000, size:0x1000000 sector:0x1000 page:0x100 id:0x1840ef\r\n\
+        start_trans\n\
+        CRC:0x00000000\r\n\
+        OK\r\n";
+
+    #[test]
+    fn parse_nor_flash_info() {
+        let info = FlashInfo::parse(NOR_OUTPUT).unwrap();
+        assert_eq!(
+            info,
+            FlashInfo {
+                address: 0x1200_0000,
+                size: 16 * 1024 * 1024,
+                sector_size: 4096,
+                page_size: 256,
+                jedec_id: 0x1840EF,
+            }
+        );
+        assert!(!info.is_nand());
+        assert_eq!(info.memory_type(), "nor");
+        assert_eq!(
+            info.to_string(),
+            "0x12000000 NOR, ID 0x1840EF, 16 MB, 4 KB sectors, 256 B pages"
+        );
+    }
+
+    #[test]
+    fn parse_nand_flash_info() {
+        let output = "addr:0x12000000, size:0x8000000 sector:0x20000 page:0x800 id:0x11f1c8\r\n";
+        let info = FlashInfo::parse(output).unwrap();
+        assert!(info.is_nand());
+        assert_eq!(info.memory_type(), "nand");
+        assert_eq!(info.page_size, 2048);
+    }
+
+    #[test]
+    fn parse_missing_flash() {
+        // 地址上没有 flash 时 stub 找不到句柄，除全局的页大小外都输出 0
+        let output = "addr:0x10000000, size:0x0 sector:0x0 page:0x100 id:0x0\r\nstart_trans\n";
+        assert_eq!(FlashInfo::parse(output), None);
+        assert_eq!(FlashInfo::parse("msh />"), None);
+    }
+
+    #[test]
+    fn memory_matches_skips_sd() {
+        let mut info = ChipInfo {
+            chip: "sf32lb52".to_string(),
+            memory_type: "nor".to_string(),
+            chip_id: None,
+            uid: None,
+            flash: vec![FlashInfo::parse(NOR_OUTPUT).unwrap()],
+        };
+        assert_eq!(info.memory_matches(), Some(true));
+        info.memory_type = "nand".to_string();
+        assert_eq!(info.memory_matches(), Some(false));
+        // stub 无法检测 SD 卡，板上的 NOR 不能说明 --memory sd 是错的
+        info.memory_type = "sd".to_string();
+        assert_eq!(info.memory_matches(), None);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::SifliDebugTool;
+use probe_rs::{CoreStatus, MemoryInterface, RegisterId};
+use std::fmt;
+use std::time::Duration;
+
+// SCB 故障状态寄存器
+const CFSR: u64 = 0xE000_ED28;
+const HFSR: u64 = 0xE000_ED2C;
+const DFSR: u64 = 0xE000_ED30;
+const MMFAR: u64 = 0xE000_ED34;
+const BFAR: u64 = 0xE000_ED38;
+
+const CORE_REGISTERS: [&str; 17] = [
+    "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9", "R10", "R11", "R12", "SP", "LR",
+    "PC", "xPSR",
+];
+
+// (位, 名称, 说明)
+const CFSR_BITS: &[(u32, &str, &str)] = &[
+    (0, "IACCVIOL", "MemManage: instruction access violation"),
+    (1, "DACCVIOL", "MemManage: data access violation"),
+    (3, "MUNSTKERR", "MemManage: fault on exception return unstacking"),
+    (4, "MSTKERR", "MemManage: fault on exception entry stacking"),
+    (5, "MLSPERR", "MemManage: fault during lazy FP state preservation"),
+    (8, "IBUSERR", "BusFault: instruction fetch bus error"),
+    (9, "PRECISERR", "BusFault: precise data bus error"),
+    (10, "IMPRECISERR", "BusFault: imprecise data bus error"),
+    (11, "UNSTKERR", "BusFault: fault on exception return unstacking"),
+    (12, "STKERR", "BusFault: fault on exception entry stacking"),
+    (13, "LSPERR", "BusFault: fault during lazy FP state preservation"),
+    (16, "UNDEFINSTR", "UsageFault: undefined instruction"),
+    (17, "INVSTATE", "UsageFault: invalid state (e.g. branch to an even address)"),
+    (18, "INVPC", "UsageFault: invalid EXC_RETURN on exception return"),
+    (19, "NOCP", "UsageFault: coprocessor not present or disabled"),
+    (20, "STKOF", "UsageFault: stack overflow (stack limit register)"),
+    (24, "UNALIGNED", "UsageFault: unaligned access"),
+    (25, "DIVBYZERO", "UsageFault: divide by zero"),
+];
+
+const HFSR_BITS: &[(u32, &str, &str)] = &[
+    (1, "VECTTBL", "HardFault: bus fault on vector table read"),
+    (30, "FORCED", "HardFault: escalated from a configurable fault"),
+    (31, "DEBUGEVT", "HardFault: debug event while debugging was disabled"),
+];
+
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+
+// DCRSR 中 MSP 和 PSP 的寄存器编号
+const MSP: u16 = 17;
+const PSP: u16 = 18;
+
+// 异常入栈的 R0、R1、R2、R3、R12、LR、PC、xPSR
+const FRAME_WORDS: usize = 8;
+
+/// 异常入口时硬件压栈的寄存器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackedFrame {
+    /// 栈帧所在的地址（MSP 或 PSP）
+    pub sp: u32,
+    /// 栈帧所在的栈，"MSP" 或 "PSP"
+    pub stack: &'static str,
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+impl StackedFrame {
+    fn from_words(stack: &'static str, sp: u32, words: &[u32; FRAME_WORDS]) -> Self {
+        StackedFrame {
+            sp,
+            stack,
+            r0: words[0],
+            r1: words[1],
+            r2: words[2],
+            r3: words[3],
+            r12: words[4],
+            lr: words[5],
+            pc: words[6],
+            xpsr: words[7],
+        }
+    }
+}
+
+/// 根据 EXC_RETURN 选择异常栈帧所在的栈，LR 不是 EXC_RETURN 时返回 None
+fn exception_stack(exc_return: u32, msp: u32, psp: u32) -> Option<(&'static str, u32)> {
+    if exc_return & 0xFF00_0000 != 0xFF00_0000 {
+        return None;
+    }
+    // bit 2 (SPSEL) 为 1 表示返回时使用 PSP
+    if exc_return & (1 << 2) != 0 {
+        Some(("PSP", psp))
+    } else {
+        Some(("MSP", msp))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreStatusReport {
+    /// 暂停前内核的状态
+    pub state: String,
+    /// R0-R12、SP、LR、PC、xPSR
+    pub registers: Vec<(&'static str, u32)>,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub dfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    /// 处于异常中时，异常入口压栈的寄存器，其中的 PC 和 LR 指向出错的代码
+    pub stacked: Option<StackedFrame>,
+}
+
+pub trait CoreStatusTrait {
+    /// 暂停内核并读取寄存器和故障状态，`resume` 为 true 时读取完成后恢复运行
+    fn core_status(&mut self, resume: bool) -> Result<CoreStatusReport, std::io::Error>;
+}
+
+impl CoreStatusReport {
+    pub fn register(&self, name: &str) -> Option<u32> {
+        self.registers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// 根据 xPSR 中的 IPSR 字段得到当前的异常
+    pub fn exception(&self) -> Option<String> {
+        let number = self.register("xPSR")? & 0x1FF;
+        Some(match number {
+            0 => "Thread mode".to_string(),
+            1 => "Reset".to_string(),
+            2 => "NMI".to_string(),
+            3 => "HardFault".to_string(),
+            4 => "MemManage".to_string(),
+            5 => "BusFault".to_string(),
+            6 => "UsageFault".to_string(),
+            7 => "SecureFault".to_string(),
+            11 => "SVCall".to_string(),
+            12 => "DebugMonitor".to_string(),
+            14 => "PendSV".to_string(),
+            15 => "SysTick".to_string(),
+            n if n >= 16 => format!("IRQ {}", n - 16),
+            n => format!("Reserved exception {}", n),
+        })
+    }
+
+    /// 解码 CFSR 和 HFSR 中置位的故障原因
+    pub fn fault_reasons(&self) -> Vec<String> {
+        let mut reasons = Vec::new();
+        for (bit, name, description) in HFSR_BITS.iter() {
+            if self.hfsr & (1 << bit) != 0 {
+                reasons.push(format!("{}: {}", name, description));
+            }
+        }
+        for (bit, name, description) in CFSR_BITS.iter() {
+            if self.cfsr & (1 << bit) != 0 {
+                reasons.push(format!("{}: {}", name, description));
+            }
+        }
+        if self.cfsr & MMARVALID != 0 {
+            reasons.push(format!("MemManage fault address: 0x{:08X}", self.mmfar));
+        }
+        if self.cfsr & BFARVALID != 0 {
+            reasons.push(format!("BusFault address: 0x{:08X}", self.bfar));
+        }
+        reasons
+    }
+}
+
+impl fmt::Display for CoreStatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "State:     {}", self.state)?;
+        if let Some(exception) = self.exception() {
+            writeln!(f, "Exception: {}", exception)?;
+        }
+        writeln!(f)?;
+        for row in self.registers.chunks(4) {
+            let line: Vec<String> = row
+                .iter()
+                .map(|(name, value)| format!("{:>4} = 0x{:08X}", name, value))
+                .collect();
+            writeln!(f, "{}", line.join("  "))?;
+        }
+        if let Some(frame) = &self.stacked {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Stacked frame ({} = 0x{:08X}):",
+                frame.stack, frame.sp
+            )?;
+            writeln!(
+                f,
+                "  PC = 0x{:08X}    LR = 0x{:08X}  xPSR = 0x{:08X}",
+                frame.pc, frame.lr, frame.xpsr
+            )?;
+            writeln!(
+                f,
+                "  R0 = 0x{:08X}    R1 = 0x{:08X}    R2 = 0x{:08X}    R3 = 0x{:08X}   R12 = 0x{:08X}",
+                frame.r0, frame.r1, frame.r2, frame.r3, frame.r12
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "CFSR  = 0x{:08X}  HFSR = 0x{:08X}  DFSR = 0x{:08X}",
+            self.cfsr, self.hfsr, self.dfsr
+        )?;
+        writeln!(f, "MMFAR = 0x{:08X}  BFAR = 0x{:08X}", self.mmfar, self.bfar)?;
+
+        let reasons = self.fault_reasons();
+        if reasons.is_empty() {
+            write!(f, "No fault recorded")
+        } else {
+            writeln!(f, "Fault reasons:")?;
+            for (i, reason) in reasons.iter().enumerate() {
+                if i > 0 {
+                    writeln!(f)?;
+                }
+                write!(f, "  - {}", reason)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl CoreStatusTrait for SifliDebugTool {
+    fn core_status(&mut self, resume: bool) -> Result<CoreStatusReport, std::io::Error> {
+        let mut core = self.core()?;
+        let state = match core
+            .status()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        {
+            CoreStatus::Running => "running".to_string(),
+            CoreStatus::Halted(reason) => format!("halted ({:?})", reason),
+            CoreStatus::LockedUp => "locked up".to_string(),
+            CoreStatus::Sleeping => "sleeping".to_string(),
+            CoreStatus::Unknown => "unknown".to_string(),
+        };
+
+        // 不复位，直接暂停内核，保留故障现场
+        core.halt(Duration::from_secs(1))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        let mut registers = Vec::with_capacity(CORE_REGISTERS.len());
+        for (id, name) in CORE_REGISTERS.iter().enumerate() {
+            let value: u32 = core
+                .read_core_reg(RegisterId(id as u16))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            registers.push((*name, value));
+        }
+
+        let mut report = CoreStatusReport {
+            state,
+            registers,
+            cfsr: 0,
+            hfsr: 0,
+            dfsr: 0,
+            mmfar: 0,
+            bfar: 0,
+            stacked: None,
+        };
+
+        // 处于异常中时，LR 为 EXC_RETURN，出错代码的 PC 和 LR 保存在异常栈帧中
+        let in_exception = report.register("xPSR").unwrap_or(0) & 0x1FF != 0;
+        let lr = report.register("LR").unwrap_or(0);
+        if in_exception {
+            let mut read_reg = |id| -> Result<u32, std::io::Error> {
+                core.read_core_reg(RegisterId(id))
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            };
+            let msp = read_reg(MSP)?;
+            let psp = read_reg(PSP)?;
+            if let Some((stack, sp)) = exception_stack(lr, msp, psp) {
+                let mut words = [0u32; FRAME_WORDS];
+                core.read_32(sp as u64, &mut words)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                report.stacked = Some(StackedFrame::from_words(stack, sp, &words));
+            }
+        }
+
+        let mut read = |address| {
+            core.read_word_32(address)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        };
+        report.cfsr = read(CFSR)?;
+        report.hfsr = read(HFSR)?;
+        report.dfsr = read(DFSR)?;
+        report.mmfar = read(MMFAR)?;
+        report.bfar = read(BFAR)?;
+
+        if resume {
+            core.run()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(xpsr: u32, cfsr: u32, hfsr: u32) -> CoreStatusReport {
+        CoreStatusReport {
+            state: "halted".to_string(),
+            registers: vec![("xPSR", xpsr)],
+            cfsr,
+            hfsr,
+            dfsr: 0,
+            mmfar: 0x2000_1000,
+            bfar: 0x6000_0004,
+            stacked: None,
+        }
+    }
+
+    #[test]
+    fn decode_fault_registers() {
+        // FORCED + PRECISERR + BFARVALID
+        let status = report(3, (1 << 9) | BFARVALID, 1 << 30);
+        assert_eq!(status.exception().as_deref(), Some("HardFault"));
+        let reasons = status.fault_reasons();
+        assert_eq!(reasons.len(), 3);
+        assert!(reasons[0].starts_with("FORCED:"));
+        assert!(reasons[1].starts_with("PRECISERR:"));
+        assert_eq!(reasons[2], "BusFault address: 0x60000004");
+
+        // UNDEFINSTR + DIVBYZERO + MMARVALID，没有 BFARVALID 时不显示 BFAR
+        let status = report(0, (1 << 16) | (1 << 25) | MMARVALID, 0);
+        assert_eq!(status.exception().as_deref(), Some("Thread mode"));
+        let reasons = status.fault_reasons();
+        assert_eq!(reasons.len(), 3);
+        assert!(reasons[0].starts_with("UNDEFINSTR:"));
+        assert!(reasons[1].starts_with("DIVBYZERO:"));
+        assert_eq!(reasons[2], "MemManage fault address: 0x20001000");
+
+        assert!(report(16 + 5, 0, 0).fault_reasons().is_empty());
+        assert_eq!(report(16 + 5, 0, 0).exception().as_deref(), Some("IRQ 5"));
+    }
+
+    #[test]
+    fn exception_stack_from_exc_return() {
+        let msp = 0x2000_8000;
+        let psp = 0x2000_4000;
+        assert_eq!(exception_stack(0xFFFF_FFF9, msp, psp), Some(("MSP", msp)));
+        assert_eq!(exception_stack(0xFFFF_FFF1, msp, psp), Some(("MSP", msp)));
+        assert_eq!(exception_stack(0xFFFF_FFFD, msp, psp), Some(("PSP", psp)));
+        assert_eq!(exception_stack(0xFFFF_FFED, msp, psp), Some(("PSP", psp)));
+        // 普通的返回地址不是 EXC_RETURN
+        assert_eq!(exception_stack(0x1000_0125, msp, psp), None);
+    }
+
+    #[test]
+    fn stacked_frame_layout() {
+        let words = [0, 1, 2, 3, 12, 0x1000_0201, 0x1000_0300, 0x0100_0000];
+        let frame = StackedFrame::from_words("PSP", 0x2000_4000, &words);
+        assert_eq!(frame.r3, 3);
+        assert_eq!(frame.r12, 12);
+        assert_eq!(frame.lr, 0x1000_0201);
+        assert_eq!(frame.pc, 0x1000_0300);
+        assert_eq!(frame.xpsr, 0x0100_0000);
+    }
+}
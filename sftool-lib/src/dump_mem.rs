@@ -0,0 +1,169 @@
+use crate::SifliDebugTool;
+use indicatif::ProgressStyle;
+use probe_rs::MemoryInterface;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// 原始二进制
+    Raw,
+    /// 只包含一个 PT_LOAD 段的 ELF32 core 文件，可直接交给 GDB 等工具分析
+    Elf,
+}
+
+#[derive(Debug, Clone)]
+pub struct DumpMemParams {
+    pub address: u32,
+    pub length: u32,
+    pub file_path: String,
+    pub format: DumpFormat,
+}
+
+pub trait DumpMemTrait {
+    fn dump_mem(&mut self, params: &DumpMemParams) -> Result<(), std::io::Error>;
+}
+
+const ELF_HEADER_SIZE: u16 = 52;
+const PROGRAM_HEADER_SIZE: u16 = 32;
+const EM_ARM: u16 = 40;
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+// PF_X | PF_W | PF_R
+const PF_RWX: u32 = 7;
+
+// 生成 ELF32 小端 core 文件头以及唯一的 PT_LOAD 程序头，数据紧跟在其后
+fn elf_core_header(address: u32, length: u32) -> Vec<u8> {
+    let data_offset = (ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE) as u32;
+    let mut header = Vec::with_capacity(data_offset as usize);
+
+    // e_ident: 魔数、ELFCLASS32、ELFDATA2LSB、EV_CURRENT，其余填 0
+    header.extend_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1, 1]);
+    header.extend_from_slice(&[0u8; 9]);
+    header.extend_from_slice(&ET_CORE.to_le_bytes());
+    header.extend_from_slice(&EM_ARM.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    header.extend_from_slice(&0u32.to_le_bytes()); // e_entry
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u32).to_le_bytes()); // e_phoff
+    header.extend_from_slice(&0u32.to_le_bytes()); // e_shoff
+    header.extend_from_slice(&0x0500_0000u32.to_le_bytes()); // e_flags: EABI version 5
+    header.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&PROGRAM_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    header.extend_from_slice(&0u16.to_le_bytes()); // e_shentsize
+    header.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    header.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+    header.extend_from_slice(&PT_LOAD.to_le_bytes());
+    header.extend_from_slice(&data_offset.to_le_bytes()); // p_offset
+    header.extend_from_slice(&address.to_le_bytes()); // p_vaddr
+    header.extend_from_slice(&address.to_le_bytes()); // p_paddr
+    header.extend_from_slice(&length.to_le_bytes()); // p_filesz
+    header.extend_from_slice(&length.to_le_bytes()); // p_memsz
+    header.extend_from_slice(&PF_RWX.to_le_bytes());
+    header.extend_from_slice(&4u32.to_le_bytes()); // p_align
+
+    header
+}
+
+fn check_range(address: u32, length: u32) -> Result<(), std::io::Error> {
+    if length == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Dump length must not be zero",
+        ));
+    }
+    if address.checked_add(length - 1).is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Range 0x{:08X} + 0x{:X} exceeds the 32-bit address space",
+                address, length
+            ),
+        ));
+    }
+    Ok(())
+}
+
+impl DumpMemTrait for SifliDebugTool {
+    fn dump_mem(&mut self, params: &DumpMemParams) -> Result<(), std::io::Error> {
+        check_range(params.address, params.length)?;
+
+        let mut writer = BufWriter::new(File::create(&params.file_path)?);
+        if params.format == DumpFormat::Elf {
+            writer.write_all(&elf_core_header(params.address, params.length))?;
+        }
+
+        let progress_bar = self.base.new_progress_bar(params.length as u64);
+        if !self.base.quiet {
+            progress_bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("[{prefix}] Dump at {msg}... {wide_bar} {bytes_per_sec} {percent_precise}%")
+                    .unwrap()
+                    .progress_chars("=>-"),
+            );
+            progress_bar.set_message(format!("0x{:08X}", params.address));
+            progress_bar.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+        }
+
+        // 不复位、不暂停内核，直接通过 AHB 读取，固件可以继续运行
+        let packet_size = self.packet_size() as u32;
+        let mut address = params.address as u64;
+        let mut remaining = params.length;
+        let mut buffer = vec![0u8; packet_size as usize];
+        let mut core = self.core()?;
+        while remaining > 0 {
+            let len = std::cmp::min(remaining, packet_size) as usize;
+            core.read(address, &mut buffer[..len])
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            writer.write_all(&buffer[..len])?;
+            progress_bar.inc(len as u64);
+            address += len as u64;
+            remaining -= len as u32;
+        }
+        drop(core);
+        writer.flush()?;
+
+        if !self.base.quiet {
+            progress_bar.finish_with_message(format!("Saved to {}", params.file_path));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_checks() {
+        assert!(check_range(0x2000_0000, 0x8_0000).is_ok());
+        assert!(check_range(0xFFFF_FF00, 0x100).is_ok());
+        assert!(check_range(0xFFFF_FF00, 0x101).is_err());
+        assert!(check_range(0x2000_0000, 0).is_err());
+    }
+
+    #[test]
+    fn elf_core_header_layout() {
+        let header = elf_core_header(0x2000_0000, 0x1000);
+        assert_eq!(header.len(), 84);
+        assert_eq!(&header[..7], &[0x7F, b'E', b'L', b'F', 1, 1, 1]);
+        let u16_at = |offset: usize| u16::from_le_bytes([header[offset], header[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        assert_eq!(u16_at(16), ET_CORE);
+        assert_eq!(u16_at(18), EM_ARM);
+        assert_eq!(u32_at(28), 52); // e_phoff
+        assert_eq!(u16_at(44), 1); // e_phnum
+
+        // 唯一的 PT_LOAD 段指向紧跟在头部之后的数据
+        assert_eq!(u32_at(52), PT_LOAD);
+        assert_eq!(u32_at(56), 84); // p_offset
+        assert_eq!(u32_at(60), 0x2000_0000); // p_vaddr
+        assert_eq!(u32_at(64), 0x2000_0000); // p_paddr
+        assert_eq!(u32_at(68), 0x1000); // p_filesz
+        assert_eq!(u32_at(72), 0x1000); // p_memsz
+        assert_eq!(u32_at(76), PF_RWX);
+    }
+}
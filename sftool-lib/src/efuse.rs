@@ -0,0 +1,318 @@
+use crate::ram_command::{Command, RamCommand, parse_hex_output};
+use crate::{SifliTool, hex};
+use indicatif::ProgressStyle;
+use phf::phf_map;
+use std::fmt;
+use std::time::Duration;
+
+/// eFuse 中的一个字段，位置以 bit 为单位
+#[derive(Debug, Clone, Copy)]
+pub struct EfuseField {
+    pub name: &'static str,
+    pub bit_offset: u32,
+    pub bytes: u32,
+    pub description: &'static str,
+}
+
+// SF32LB52 的 eFuse 共 4 个 bank，每个 bank 256 bit。bank1 的校准字段按 stub 启动时的解析方式划分，
+// 按字节访问，相邻字段可能共用边界上的字节；修订号为 7 的芯片 PMU 校准值另外放在 bank1 的 20~28 字节。
+// 安全配置和锁定位的布局 stub 中没有使用，请按 SDK 用 `BITOFF:BYTES` 访问
+static EFUSE_FIELDS: phf::Map<&'static str, &'static [EfuseField]> = phf_map! {
+    "sf32lb52" => &[
+        EfuseField { name: "uid", bit_offset: 0, bytes: 16, description: "Chip unique ID" },
+        EfuseField { name: "bank0", bit_offset: 0, bytes: 32, description: "Bank 0: UID and security configuration" },
+        EfuseField { name: "bank1", bit_offset: 256, bytes: 32, description: "Bank 1: factory calibration" },
+        EfuseField { name: "pmu_trim", bit_offset: 256, bytes: 4, description: "PMU BUCK/LDO trim (bank 1 bytes 0-3, byte 0 is zero when uncalibrated)" },
+        EfuseField { name: "adc_cal", bit_offset: 288, bytes: 7, description: "ADC calibration (bank 1 bytes 4-10)" },
+        EfuseField { name: "charger_cal", bit_offset: 336, bytes: 6, description: "Charger calibration (bank 1 bytes 10-15)" },
+        EfuseField { name: "bank2", bit_offset: 512, bytes: 32, description: "Bank 2: user data" },
+        EfuseField { name: "bank3", bit_offset: 768, bytes: 32, description: "Bank 3: user data" },
+    ],
+};
+
+// 不在 eFuse 中、而是存放在 OTP 出厂配置里的字段
+static OTP_FIELDS: phf::Map<&'static str, &'static str> = phf_map! {
+    "mac" => "FACTORY_CFG_ID_MAC",
+};
+
+const EFUSE_BITS: u32 = 1024;
+// stub 的 efuse_read/efuse_write 每次最多处理 32 字节
+const MAX_BYTES_PER_COMMAND: u32 = 32;
+
+/// 要读写的 eFuse 区域：字段名，或 `BITOFF:BYTES` 形式的原始位置
+#[derive(Debug, Clone)]
+pub enum EfuseTarget {
+    Field(String),
+    Raw { bit_offset: u32, bytes: u32 },
+}
+
+impl EfuseTarget {
+    pub fn parse(s: &str) -> Result<Self, std::io::Error> {
+        let Some((bit_offset, bytes)) = s.split_once(':') else {
+            return Ok(EfuseTarget::Field(s.to_lowercase()));
+        };
+        let parse = |v: &str| crate::write_flash::str_to_u32(v.to_lowercase().as_str());
+        match (parse(bit_offset), parse(bytes)) {
+            (Ok(bit_offset), Ok(bytes)) => Ok(EfuseTarget::Raw { bit_offset, bytes }),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid eFuse location '{}', expected a field name or BITOFF:BYTES", s),
+            )),
+        }
+    }
+}
+
+/// 返回芯片已知的 eFuse 字段
+pub fn efuse_fields(chip: &str) -> &'static [EfuseField] {
+    EFUSE_FIELDS.get(chip).copied().unwrap_or_default()
+}
+
+/// 一次 eFuse 烧写的预览，eFuse 只能从 0 烧成 1
+#[derive(Debug, Clone)]
+pub struct EfuseWritePlan {
+    pub bit_offset: u32,
+    pub current: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl EfuseWritePlan {
+    fn bits(&self, pred: impl Fn(bool, bool) -> bool) -> Vec<u32> {
+        let mut bits = Vec::new();
+        for (i, (cur, new)) in self.current.iter().zip(self.new.iter()).enumerate() {
+            for bit in 0..8 {
+                if pred(cur & (1 << bit) != 0, new & (1 << bit) != 0) {
+                    bits.push(self.bit_offset + i as u32 * 8 + bit);
+                }
+            }
+        }
+        bits
+    }
+
+    /// 需要烧写的 bit（当前为 0，目标为 1）
+    pub fn bits_to_burn(&self) -> Vec<u32> {
+        self.bits(|cur, new| !cur && new)
+    }
+
+    /// 已经烧写、无法再改回 0 的 bit
+    pub fn conflicting_bits(&self) -> Vec<u32> {
+        self.bits(|cur, new| cur && !new)
+    }
+}
+
+impl fmt::Display for EfuseWritePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Bit offset: {}", self.bit_offset)?;
+        writeln!(f, "Current:    {}", hex(&self.current))?;
+        writeln!(f, "New:        {}", hex(&self.new))?;
+        let burn = self.bits_to_burn();
+        let bits: Vec<String> = burn.iter().map(|b| b.to_string()).collect();
+        write!(f, "Bits to burn ({}): [{}]", burn.len(), bits.join(", "))?;
+        let conflicts = self.conflicting_bits();
+        if !conflicts.is_empty() {
+            let bits: Vec<String> = conflicts.iter().map(|b| b.to_string()).collect();
+            write!(
+                f,
+                "\nAlready burned, cannot be cleared ({}): [{}]",
+                conflicts.len(),
+                bits.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub trait EfuseTrait {
+    /// 当前芯片已知的 eFuse 字段
+    fn efuse_fields(&self) -> &'static [EfuseField];
+    /// 读取 eFuse，返回 `(字段, 数据)`
+    fn efuse_read(&mut self, target: &EfuseTarget) -> Result<(EfuseField, Vec<u8>), std::io::Error>;
+    /// 读取当前值并生成烧写预览，不会修改 eFuse
+    fn efuse_plan(&mut self, target: &EfuseTarget, data: &[u8]) -> Result<EfuseWritePlan, std::io::Error>;
+    /// 按预览烧写 eFuse 并回读校验，该操作不可逆
+    fn efuse_write(&mut self, plan: &EfuseWritePlan) -> Result<(), std::io::Error>;
+}
+
+impl SifliTool {
+    fn resolve_efuse_target(&self, target: &EfuseTarget) -> Result<EfuseField, std::io::Error> {
+        let field = match target {
+            EfuseTarget::Field(name) => *efuse_fields(&self.base.chip)
+                .iter()
+                .find(|field| field.name == name)
+                .ok_or_else(|| {
+                    if let Some(id) = OTP_FIELDS.get(name.as_str()) {
+                        return std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!(
+                                "'{}' is not stored in eFuse but in the OTP factory configuration ({}), it is included in the factory_cali report",
+                                name, id
+                            ),
+                        );
+                    }
+                    let names: Vec<&str> = efuse_fields(&self.base.chip)
+                        .iter()
+                        .map(|field| field.name)
+                        .collect();
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Unknown eFuse field '{}' (known: [{}]), or use BITOFF:BYTES",
+                            name,
+                            names.join(", ")
+                        ),
+                    )
+                })?,
+            EfuseTarget::Raw { bit_offset, bytes } => EfuseField {
+                name: "raw",
+                bit_offset: *bit_offset,
+                bytes: *bytes,
+                description: "",
+            },
+        };
+        if field.bit_offset % 8 != 0
+            || field.bytes == 0
+            || field
+                .bytes
+                .checked_mul(8)
+                .and_then(|bits| bits.checked_add(field.bit_offset))
+                .is_none_or(|end| end > EFUSE_BITS)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid eFuse range: bit offset {} ({} bytes), the offset must be a multiple of 8 and within {} bits",
+                    field.bit_offset, field.bytes, EFUSE_BITS
+                ),
+            ));
+        }
+        Ok(field)
+    }
+
+    fn efuse_read_raw(&mut self, bit_offset: u32, bytes: u32) -> Result<Vec<u8>, std::io::Error> {
+        let mut data = Vec::with_capacity(bytes as usize);
+        let mut offset = bit_offset;
+        let mut remaining = bytes;
+        while remaining > 0 {
+            let len = std::cmp::min(remaining, MAX_BYTES_PER_COMMAND);
+            let output = self.command_output(
+                Command::EfuseRead {
+                    bit_offset: offset,
+                    bytes: len,
+                },
+                &["EFUSE_READ_PASS", "EFUSE_READ_FAIL"],
+            )?;
+            let chunk = if output.contains("EFUSE_READ_FAIL") {
+                None
+            } else {
+                parse_hex_output(&output, "EFUSE_READ_VALUE:", "EFUSE_READ_PASS")
+            };
+            let Some(chunk) = chunk.filter(|c| c.len() == len as usize) else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Failed to read eFuse at bit offset {}", offset),
+                ));
+            };
+            data.extend(chunk);
+            offset += len * 8;
+            remaining -= len;
+        }
+        Ok(data)
+    }
+}
+
+impl EfuseTrait for SifliTool {
+    fn efuse_fields(&self) -> &'static [EfuseField] {
+        efuse_fields(&self.base.chip)
+    }
+
+    fn efuse_read(&mut self, target: &EfuseTarget) -> Result<(EfuseField, Vec<u8>), std::io::Error> {
+        let field = self.resolve_efuse_target(target)?;
+        let data = self.efuse_read_raw(field.bit_offset, field.bytes)?;
+        Ok((field, data))
+    }
+
+    fn efuse_plan(&mut self, target: &EfuseTarget, data: &[u8]) -> Result<EfuseWritePlan, std::io::Error> {
+        let field = self.resolve_efuse_target(target)?;
+        if data.len() != field.bytes as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "eFuse field '{}' is {} bytes, but {} bytes were given",
+                    field.name,
+                    field.bytes,
+                    data.len()
+                ),
+            ));
+        }
+        let current = self.efuse_read_raw(field.bit_offset, field.bytes)?;
+        Ok(EfuseWritePlan {
+            bit_offset: field.bit_offset,
+            current,
+            new: data.to_vec(),
+        })
+    }
+
+    fn efuse_write(&mut self, plan: &EfuseWritePlan) -> Result<(), std::io::Error> {
+        if !plan.conflicting_bits().is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The new value clears bits that are already burned",
+            ));
+        }
+        if plan.bits_to_burn().is_empty() {
+            return Ok(());
+        }
+
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+            spinner.set_message("Burning eFuse...");
+        }
+
+        let chunk_size = MAX_BYTES_PER_COMMAND as usize;
+        for (i, (new, current)) in plan
+            .new
+            .chunks(chunk_size)
+            .zip(plan.current.chunks(chunk_size))
+            .enumerate()
+        {
+            // 没有需要烧写的 bit 的块直接跳过
+            if new == current {
+                continue;
+            }
+            let output = self.command_output(
+                Command::EfuseWrite {
+                    bit_offset: plan.bit_offset + (i * chunk_size * 8) as u32,
+                    bytes: new.len() as u32,
+                    data: hex(new),
+                },
+                &["EFUSE_WRITE_PASS", "EFUSE_WRITE_FAIL"],
+            )?;
+            if !output.contains("EFUSE_WRITE_PASS") {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "eFuse write failed",
+                ));
+            }
+        }
+
+        let readback = self.efuse_read_raw(plan.bit_offset, plan.new.len() as u32)?;
+        if readback != plan.new {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "eFuse verify failed, expected {} but read {}",
+                    hex(&plan.new),
+                    hex(&readback)
+                ),
+            ));
+        }
+
+        if !self.base.quiet {
+            spinner.finish_with_message("eFuse burned and verified");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use crate::SifliTool;
+use crate::ram_command::{Command, RamCommand};
+use indicatif::ProgressStyle;
+use serde::Serialize;
+use std::time::Duration;
+
+/// 晶振校准参数，对应 `crystal_cali <pin> <ppm> <timeout>`
+#[derive(Debug, Clone)]
+pub struct CrystalCaliParams {
+    /// 输入参考时钟的引脚编号，例如 PB08 为 8
+    pub pin: u32,
+    /// 允许的频偏，单位 ppm
+    pub ppm: u32,
+    /// 校准超时时间，单位秒
+    pub timeout: u32,
+}
+
+/// 电池电压校准参数，对应 `battery_cali <voltage> <range> <tolerance> <r1> <r2>`
+#[derive(Debug, Clone)]
+pub struct BatteryCaliParams {
+    /// 电源输出的参考电压，单位 mV
+    pub voltage: u32,
+    /// 校准前测量值与参考电压的最大偏差，单位 mV
+    pub range: u32,
+    /// 校准后允许的误差，单位 mV
+    pub tolerance: u32,
+    /// 分压电阻，单位 kΩ
+    pub r1: u32,
+    pub r2: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct FactoryCaliParams {
+    /// 为 `None` 时跳过该项校准
+    pub crystal: Option<CrystalCaliParams>,
+    pub battery: Option<BatteryCaliParams>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaliResult {
+    pub passed: bool,
+    /// stub 输出的校准值，晶振为负载电容配置，电池为 ADC 校准值
+    pub value: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FactoryCaliReport {
+    pub port: String,
+    pub chip: String,
+    pub passed: bool,
+    pub crystal: Option<CaliResult>,
+    pub battery: Option<CaliResult>,
+    /// 从 OTP 读回的 MAC 地址
+    pub mac: Option<String>,
+    /// 从 OTP 读回的晶振校准值
+    pub crystal_stored: Option<u32>,
+}
+
+pub trait FactoryCaliTrait {
+    /// 依次执行晶振和电池校准，校准结果由 stub 写入 OTP。
+    /// 校准失败不会返回错误，而是记录在报告中
+    fn factory_cali(
+        &mut self,
+        params: &FactoryCaliParams,
+    ) -> Result<FactoryCaliReport, std::io::Error>;
+}
+
+// 解析 `marker` 之后的数值，支持 `0x` 开头的十六进制和十进制
+fn parse_value(output: &str, marker: &str) -> Option<u32> {
+    let from = output.find(marker)? + marker.len();
+    let token = output[from..].split_whitespace().next()?;
+    match token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => token.parse().ok(),
+    }
+}
+
+impl SifliTool {
+    fn cali_step(
+        &mut self,
+        cmd: Command,
+        pass: &str,
+        fail: &str,
+        message: &str,
+    ) -> Result<CaliResult, std::io::Error> {
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+            spinner.set_message(format!("{}...", message));
+        }
+
+        let output = self.command_output(cmd, &[pass, fail])?;
+        let result = if output.contains(pass) {
+            CaliResult {
+                passed: true,
+                value: parse_value(&output, pass),
+            }
+        } else {
+            CaliResult {
+                passed: false,
+                value: parse_value(&output, fail),
+            }
+        };
+
+        if !self.base.quiet {
+            let status = if result.passed { "passed" } else { "failed" };
+            match result.value {
+                Some(value) => {
+                    spinner.finish_with_message(format!("{} {} (0x{:X})", message, status, value))
+                }
+                None => spinner.finish_with_message(format!("{} {}", message, status)),
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl FactoryCaliTrait for SifliTool {
+    fn factory_cali(
+        &mut self,
+        params: &FactoryCaliParams,
+    ) -> Result<FactoryCaliReport, std::io::Error> {
+        let crystal = match &params.crystal {
+            Some(crystal) => Some(self.cali_step(
+                Command::CrystalCali {
+                    pin: crystal.pin,
+                    ppm: crystal.ppm,
+                    timeout: crystal.timeout,
+                },
+                "CRYSTAL_CALI_PASS",
+                "CRYSTAL_CALI_FAIL",
+                "Crystal calibration",
+            )?),
+            None => None,
+        };
+        let battery = match &params.battery {
+            Some(battery) => Some(self.cali_step(
+                Command::BatteryCali {
+                    voltage: battery.voltage,
+                    range: battery.range,
+                    tolerance: battery.tolerance,
+                    r1: battery.r1,
+                    r2: battery.r2,
+                },
+                "BATTERY_CALI_PASS",
+                "BATTERY_CALI_FAIL",
+                "Battery calibration",
+            )?),
+            None => None,
+        };
+
+        // 读回 OTP 中保存的出厂信息，读取失败不影响校准结果
+        let (mac, crystal_stored) =
+            match self.command_output(Command::OtpFactoryRead, &["OTP_FACTORY_READ_OVER"]) {
+                Ok(output) => (
+                    output
+                        .find("FACTORY_CFG_ID_MAC: ")
+                        .and_then(|from| {
+                            output[from + "FACTORY_CFG_ID_MAC: ".len()..]
+                                .split_whitespace()
+                                .next()
+                        })
+                        .map(|mac| mac.to_uppercase()),
+                    parse_value(&output, "FACTORY_CFG_ID_CRYSTAL: "),
+                ),
+                Err(e) => {
+                    tracing::warn!("Failed to read factory OTP: {}", e);
+                    (None, None)
+                }
+            };
+
+        let passed =
+            crystal.as_ref().is_none_or(|r| r.passed) && battery.as_ref().is_none_or(|r| r.passed);
+        Ok(FactoryCaliReport {
+            port: self.base.port_name.clone(),
+            chip: self.base.chip.clone(),
+            passed,
+            crystal,
+            battery,
+            mac,
+            crystal_stored,
+        })
+    }
+}
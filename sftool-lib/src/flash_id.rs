@@ -0,0 +1,26 @@
+use crate::SifliTool;
+use crate::chip_info::FlashInfo;
+
+/// stub 识别到的 flash，容量、扇区和页大小都来自 stub 按 JEDEC ID 查到的参数
+#[derive(Debug, Clone)]
+pub struct FlashDetails {
+    pub info: FlashInfo,
+}
+
+impl FlashDetails {
+    pub fn size(&self) -> u64 {
+        self.info.size
+    }
+}
+
+pub trait FlashIdTrait {
+    /// 读取 `address` 所在 flash 的信息，没有接 flash 时返回 `None`
+    fn flash_details(&mut self, address: u32) -> Result<Option<FlashDetails>, std::io::Error>;
+}
+
+impl FlashIdTrait for SifliTool {
+    fn flash_details(&mut self, address: u32) -> Result<Option<FlashDetails>, std::io::Error> {
+        let info = self.read_flash_info(address & 0xFF00_0000)?;
+        Ok(info.map(|info| FlashDetails { info }))
+    }
+}
@@ -0,0 +1,400 @@
+use crate::SifliDebugTool;
+use probe_rs::{Core, CoreStatus, MemoryInterface, RegisterId};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+// r0-r12、sp、lr、pc 的寄存器号与 probe-rs 的 RegisterId 相同，xPSR 为 16
+const REGISTER_COUNT: u16 = 17;
+const INTERRUPT: u8 = 0x03;
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// 在 qSupported 中告诉 GDB 的最大包长度
+const PACKET_SIZE: usize = 0x4000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>arm</architecture>
+  <feature name="org.gnu.gdb.arm.m-profile">
+    <reg name="r0" bitsize="32" regnum="0"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="lr" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="xpsr" bitsize="32" regnum="16"/>
+  </feature>
+</target>
+"#;
+
+#[derive(Clone)]
+pub struct GdbServerParams {
+    /// 监听的本地 TCP 端口
+    pub port: u16,
+}
+
+pub trait GdbServerTrait {
+    /// 在本地 TCP 端口上提供 GDB 远程串行协议服务，GDB 断开后继续等待下一次连接
+    fn gdb_server(&mut self, params: &GdbServerParams) -> Result<(), std::io::Error>;
+}
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+enum Action {
+    Reply(String),
+    /// 继续运行或单步后等待内核暂停
+    Resume { step: bool },
+    Detach,
+    Kill,
+}
+
+fn probe_error(e: probe_rs::Error) -> std::io::Error {
+    std::io::Error::new(ErrorKind::Other, e)
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex_u64(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+// 解析 `addr,length`
+fn parse_range(args: &str) -> Option<(u64, usize)> {
+    let (address, length) = args.split_once(',')?;
+    Some((parse_hex_u64(address)?, parse_hex_u64(length)? as usize))
+}
+
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> Result<u8, std::io::Error> {
+        let mut byte = [0u8; 1];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_packet(&mut self) -> Result<Packet, std::io::Error> {
+        loop {
+            match self.read_byte()? {
+                b'$' => break,
+                INTERRUPT => return Ok(Packet::Interrupt),
+                // 忽略 GDB 的 +/- 应答
+                _ => continue,
+            }
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let mut sum = [0u8; 2];
+        self.stream.read_exact(&mut sum)?;
+        let expected = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok());
+        if expected != Some(checksum(&data)) {
+            self.stream.write_all(b"-")?;
+            return self.read_packet();
+        }
+        self.stream.write_all(b"+")?;
+        Ok(Packet::Command(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn send_packet(&mut self, data: &str) -> Result<(), std::io::Error> {
+        // `$`、`#`、`}`、`*` 需要转义
+        let mut escaped = Vec::with_capacity(data.len());
+        for byte in data.bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                escaped.push(b'}');
+                escaped.push(byte ^ 0x20);
+            } else {
+                escaped.push(byte);
+            }
+        }
+        let mut packet = Vec::with_capacity(escaped.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum(&escaped)).as_bytes());
+        self.stream.write_all(&packet)?;
+        self.stream.flush()
+    }
+
+    // 运行期间检查 GDB 是否发送了 Ctrl+C
+    fn interrupted(&mut self) -> Result<bool, std::io::Error> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0u8; 1];
+        let res = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match res {
+            Ok(0) => Err(std::io::Error::new(ErrorKind::ConnectionAborted, "GDB disconnected")),
+            Ok(_) => Ok(byte[0] == INTERRUPT),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn read_registers(core: &mut Core) -> Result<String, probe_rs::Error> {
+    let mut out = String::new();
+    for id in 0..REGISTER_COUNT {
+        let value: u32 = core.read_core_reg(RegisterId(id))?;
+        out.push_str(&to_hex(&value.to_le_bytes()));
+    }
+    Ok(out)
+}
+
+fn write_registers(core: &mut Core, hex: &str) -> Option<()> {
+    let data = from_hex(hex)?;
+    for (id, chunk) in data.chunks_exact(4).take(REGISTER_COUNT as usize).enumerate() {
+        let value = u32::from_le_bytes(chunk.try_into().ok()?);
+        core.write_core_reg(RegisterId(id as u16), value).ok()?;
+    }
+    Some(())
+}
+
+fn handle_query(core: &mut Core, query: &str) -> String {
+    if query.starts_with("Supported") {
+        return format!("PacketSize={:x};qXfer:features:read+;hwbreak+", PACKET_SIZE);
+    }
+    if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+        let Some((offset, length)) = parse_range(args) else {
+            return "E01".to_string();
+        };
+        let offset = offset as usize;
+        if offset >= TARGET_XML.len() {
+            return "l".to_string();
+        }
+        let end = std::cmp::min(offset + length, TARGET_XML.len());
+        let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+        return format!("{}{}", prefix, &TARGET_XML[offset..end]);
+    }
+    if query == "Attached" {
+        return "1".to_string();
+    }
+    if let Some(hex) = query.strip_prefix("Rcmd,") {
+        // `monitor reset` / `monitor halt`
+        let command = from_hex(hex).map(|c| String::from_utf8_lossy(&c).trim().to_string());
+        return match command.as_deref() {
+            Some("reset") | Some("reset halt") => {
+                match core.reset_and_halt(Duration::from_secs(5)) {
+                    Ok(_) => "OK".to_string(),
+                    Err(_) => "E01".to_string(),
+                }
+            }
+            Some("halt") => match core.halt(Duration::from_secs(1)) {
+                Ok(_) => "OK".to_string(),
+                Err(_) => "E01".to_string(),
+            },
+            _ => to_hex(b"Supported commands: reset, halt\n"),
+        };
+    }
+    String::new()
+}
+
+fn handle_command(core: &mut Core, command: &str) -> Action {
+    let reply = |s: &str| Action::Reply(s.to_string());
+    let Some((kind, args)) = command.split_at_checked(1) else {
+        return reply("");
+    };
+    match kind {
+        "?" => reply("S05"),
+        "g" => match read_registers(core) {
+            Ok(registers) => Action::Reply(registers),
+            Err(_) => reply("E01"),
+        },
+        "G" => match write_registers(core, args) {
+            Some(()) => reply("OK"),
+            None => reply("E01"),
+        },
+        "p" => {
+            let Some(id) = parse_hex_u64(args).filter(|id| *id < REGISTER_COUNT as u64) else {
+                // 未知的寄存器返回空值，GDB 会显示为 unavailable
+                return reply("xxxxxxxx");
+            };
+            match core.read_core_reg::<u32>(RegisterId(id as u16)) {
+                Ok(value) => Action::Reply(to_hex(&value.to_le_bytes())),
+                Err(_) => reply("E01"),
+            }
+        }
+        "P" => {
+            let res = args.split_once('=').and_then(|(id, value)| {
+                let id = parse_hex_u64(id).filter(|id| *id < REGISTER_COUNT as u64)?;
+                let value = u32::from_le_bytes(from_hex(value)?.try_into().ok()?);
+                core.write_core_reg(RegisterId(id as u16), value).ok()
+            });
+            match res {
+                Some(()) => reply("OK"),
+                None => reply("E01"),
+            }
+        }
+        "m" => {
+            let Some((address, length)) = parse_range(args) else {
+                return reply("E01");
+            };
+            // 长度来自 GDB 的请求，按 PacketSize 限制，回复中每个字节占两个十六进制字符。
+            // 回复的数据少于请求时 GDB 会继续读取剩下的部分
+            let mut data = vec![0u8; std::cmp::min(length, PACKET_SIZE / 2)];
+            match core.read(address, &mut data) {
+                Ok(()) => Action::Reply(to_hex(&data)),
+                Err(_) => reply("E01"),
+            }
+        }
+        "M" => {
+            let res = args.split_once(':').and_then(|(range, hex)| {
+                let (address, length) = parse_range(range)?;
+                let data = from_hex(hex).filter(|d| d.len() == length)?;
+                core.write_8(address, &data).and_then(|_| core.flush()).ok()
+            });
+            match res {
+                Some(()) => reply("OK"),
+                None => reply("E01"),
+            }
+        }
+        "c" => Action::Resume { step: false },
+        "s" => Action::Resume { step: true },
+        // flash 中无法插入软件断点，软件断点和硬件断点都使用 FPB
+        "Z" | "z" => {
+            let mut parts = args.split(',');
+            let kind = parts.next();
+            let address = parts.next().and_then(parse_hex_u64);
+            let (Some("0") | Some("1"), Some(address)) = (kind, address) else {
+                return reply("");
+            };
+            let res = if command.starts_with('Z') {
+                core.set_hw_breakpoint(address)
+            } else {
+                core.clear_hw_breakpoint(address)
+            };
+            match res {
+                Ok(()) => reply("OK"),
+                Err(_) => reply("E01"),
+            }
+        }
+        "q" => Action::Reply(handle_query(core, args)),
+        "H" => reply("OK"),
+        "D" => Action::Detach,
+        "k" => Action::Kill,
+        _ => reply(""),
+    }
+}
+
+impl SifliDebugTool {
+    fn serve_connection(&mut self, stream: TcpStream) -> Result<(), std::io::Error> {
+        stream.set_nodelay(true)?;
+        let mut conn = Connection { stream };
+        let mut core = self.core()?;
+        core.halt(Duration::from_secs(1)).map_err(probe_error)?;
+
+        loop {
+            let command = match conn.read_packet() {
+                Ok(Packet::Command(command)) => command,
+                Ok(Packet::Interrupt) => continue,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if command.is_empty() {
+                conn.send_packet("")?;
+                continue;
+            }
+            tracing::debug!("GDB: {}", command);
+
+            match handle_command(&mut core, &command) {
+                Action::Reply(reply) => conn.send_packet(&reply)?,
+                Action::Resume { step: true } => {
+                    core.step().map_err(probe_error)?;
+                    conn.send_packet("S05")?;
+                }
+                Action::Resume { step: false } => {
+                    core.run().map_err(probe_error)?;
+                    loop {
+                        if conn.interrupted()? {
+                            core.halt(Duration::from_secs(1)).map_err(probe_error)?;
+                            conn.send_packet("S02")?;
+                            break;
+                        }
+                        if let CoreStatus::Halted(_) = core.status().map_err(probe_error)? {
+                            conn.send_packet("S05")?;
+                            break;
+                        }
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                }
+                Action::Detach => {
+                    conn.send_packet("OK")?;
+                    core.clear_all_hw_breakpoints().map_err(probe_error)?;
+                    core.run().map_err(probe_error)?;
+                    return Ok(());
+                }
+                Action::Kill => {
+                    core.clear_all_hw_breakpoints().map_err(probe_error)?;
+                    core.run().map_err(probe_error)?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl GdbServerTrait for SifliDebugTool {
+    fn gdb_server(&mut self, params: &GdbServerParams) -> Result<(), std::io::Error> {
+        let listener = TcpListener::bind(("127.0.0.1", params.port))?;
+        loop {
+            if !self.base.quiet {
+                println!(
+                    "Waiting for GDB on 127.0.0.1:{} (target remote :{})",
+                    params.port, params.port
+                );
+            }
+            let (stream, peer) = listener.accept()?;
+            if !self.base.quiet {
+                println!("GDB connected from {}", peer);
+            }
+            match self.serve_connection(stream) {
+                Ok(()) => {}
+                // 连接异常断开时继续等待下一次连接
+                Err(e) if matches!(
+                    e.kind(),
+                    ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+                ) => tracing::warn!("GDB connection lost: {}", e),
+                Err(e) => return Err(e),
+            }
+            if !self.base.quiet {
+                println!("GDB disconnected");
+            }
+        }
+    }
+}
@@ -1,19 +1,37 @@
+pub mod chip_info;
+pub mod core_status;
+pub mod dump_mem;
+pub mod efuse;
+pub mod factory_cali;
+pub mod flash_id;
+pub mod gdb_server;
+pub mod littlefs;
+pub mod load_ram;
+pub mod memory;
+pub mod monitor;
+pub mod nvds;
+pub mod ports;
 mod ram_command;
 mod ram_stub;
+pub mod read_flash;
+pub mod read_fs;
 pub mod reset;
+pub mod rtt;
+pub mod sd_card;
+pub mod sign_image;
 pub mod speed;
+pub mod symbolize;
 pub mod write_flash;
+pub mod write_fs;
 
 use console::Term;
 use indicatif::{ProgressBar, ProgressStyle};
 use probe_rs::architecture::arm::armv8m::Dhcsr;
-use probe_rs::architecture::arm::core::registers::cortex_m::{PC, SP};
 use probe_rs::architecture::arm::dp::DpAddress;
 use probe_rs::architecture::arm::sequences::ArmDebugSequence;
 use probe_rs::architecture::arm::FullyQualifiedApAddress;
 use probe_rs::config::Chip;
 use probe_rs::config::DebugSequence::Arm;
-use probe_rs::probe::list::Lister;
 use probe_rs::probe::sifliuart::SifliUart;
 use probe_rs::probe::{DebugProbe, DebugProbeError, Probe, ProbeCreationError};
 use probe_rs::vendor::sifli::Sifli;
@@ -21,13 +39,36 @@ use probe_rs::vendor::Vendor;
 use probe_rs::{
     Error, MemoryInterface, MemoryMappedRegister, Permissions, RegisterId, RegisterRole, Session,
 };
-use ram_stub::CHIP_FILE_NAME;
+use ram_stub::StubKind;
 use serialport;
 use serialport::SerialPort;
 use std::env;
 use std::io::{Read, Write};
 use std::time::Duration;
 
+/// 设置 `SIFLI_UART_DEBUG` 环境变量，probe-rs 只有在设置后才会枚举 SiFli UART 调试探针。
+///
+/// 连接芯片、`list_ports` 和端口选择器都依赖该变量，需要在使用本库之前调用一次。
+///
+/// # Safety
+///
+/// 内部调用 `env::set_var`，必须在启动任何其他线程之前调用，
+/// 例如多个端口并行下载时要在创建工作线程之前设置。
+pub unsafe fn enable_sifli_uart_debug() {
+    unsafe {
+        env::set_var("SIFLI_UART_DEBUG", "1");
+    }
+}
+
+/// 将字节格式化为连续的大写十六进制字符串
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// `SifliToolBase` 的 `chip` 设为该值时使用唯一受支持的芯片，`memory_type` 设为该值时
+/// 根据 NOR stub 找到的 flash 选择 NOR 或 NAND；不会检测 SD 卡
+pub const AUTO_DETECT: &str = "auto";
+
 #[derive(Clone)]
 pub struct SifliToolBase {
     pub port_name: String,
@@ -37,6 +78,9 @@ pub struct SifliToolBase {
     pub connect_attempts: i8,
     pub compat: bool,
     pub quiet: bool,
+    /// 静默模式下显示字节进度的进度条，例如多个端口并行下载时每个端口的进度条。
+    /// 写入或读取开始时会修改它的样式和长度
+    pub progress: Option<ProgressBar>,
 }
 
 #[derive(Clone)]
@@ -45,15 +89,92 @@ pub struct WriteFlashParams {
     pub verify: bool,
     pub no_compress: bool,
     pub erase_all: bool,
+    /// 校验镜像旁 `<文件>.sig` 分离签名使用的公钥（PEM），为 `None` 时不检查签名
+    pub public_key: Option<String>,
+    /// 拒绝写入没有签名文件的镜像，需要同时指定 `public_key`
+    pub require_signed: bool,
+    /// 写入镜像后通过 stub 的 `fw_enc_wr` 写入 OTP 中的固件校验码，为 `None` 时不写入。
+    /// 加密镜像需要事先用 SDK 的工具生成，sftool 按原样写入
+    pub fw_verify_code: Option<Vec<u8>>,
+}
+
+impl SifliToolBase {
+    // 静默模式下使用隐藏的进度条，避免未完成的进度条在 drop 时输出到终端
+    pub(crate) fn new_spinner(&self) -> ProgressBar {
+        if self.quiet {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new_spinner()
+        }
+    }
+
+    // 静默模式下字节进度显示在调用方提供的进度条上，前缀保留调用方设置的内容
+    pub(crate) fn new_progress_bar(&self, len: u64) -> ProgressBar {
+        match (self.quiet, &self.progress) {
+            (true, Some(bar)) => {
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "[{prefix}] {spinner} {msg} {bytes}/{total_bytes} {bytes_per_sec}",
+                    )
+                    .unwrap(),
+                );
+                bar.set_length(len);
+                bar.set_position(0);
+                bar.clone()
+            }
+            (true, None) => ProgressBar::hidden(),
+            (false, _) => ProgressBar::new(len),
+        }
+    }
 }
 
 pub struct SifliTool {
     port: Box<dyn SerialPort>,
     base: SifliToolBase,
     step: i32,
+    chip_id: Option<u32>,
+    stub: StubKind,
     write_flash_params: Option<WriteFlashParams>,
 }
 
+/// 只通过 SiFli UART 调试接口连接芯片，不复位芯片也不下载 stub，
+/// 用于读写内存、寄存器等调试操作。
+pub struct SifliDebugTool {
+    session: Session,
+    base: SifliToolBase,
+    step: i32,
+}
+
+impl SifliDebugTool {
+    pub fn new(mut base_param: SifliToolBase) -> Result<Self, std::io::Error> {
+        base_param.port_name = ports::resolve_port(&base_param.port_name)?;
+        let mut step = 0;
+        let session = if base_param.chip == AUTO_DETECT {
+            chip_info::connect_default_chip(&mut base_param, &mut step)?
+        } else {
+            let probe = ports::open_probe(&base_param.port_name)?;
+            attempt_connect(probe, &base_param, &mut step)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        };
+        Ok(Self {
+            session,
+            base: base_param,
+            step,
+        })
+    }
+
+    // 与下载 stub 时相同，兼容模式下每次只传输 256 字节
+    pub(crate) fn packet_size(&self) -> usize {
+        if self.base.compat { 256 } else { 64 * 1024 }
+    }
+
+    pub(crate) fn core(&mut self) -> Result<probe_rs::Core<'_>, std::io::Error> {
+        self.session
+            .core(0)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+    }
+}
+
 fn attempt_connect(
     mut probe: Probe,
     base_param: &SifliToolBase,
@@ -77,7 +198,7 @@ fn attempt_connect(
             *attempts -= 1;
         }
 
-        let spinner = ProgressBar::new_spinner();
+        let spinner = base_param.new_spinner();
         if !base_param.quiet {
             spinner.enable_steady_tick(Duration::from_millis(100));
             spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
@@ -110,28 +231,68 @@ fn attempt_connect(
 
 impl SifliTool {
     pub fn new(base_param: SifliToolBase, write_flash_params: Option<WriteFlashParams>) -> Self {
-        let step = Self::download_stub(&base_param).unwrap();
+        Self::try_new(base_param, write_flash_params).unwrap()
+    }
+
+    /// 与 `new` 相同，但在连接或下载 stub 失败时返回错误而不是 panic，
+    /// 便于同时操作多个端口时单独统计每个端口的结果。
+    pub fn try_new(
+        mut base_param: SifliToolBase,
+        write_flash_params: Option<WriteFlashParams>,
+    ) -> Result<Self, std::io::Error> {
+        base_param.port_name = ports::resolve_port(&base_param.port_name)?;
+        if base_param.memory_type != AUTO_DETECT {
+            return Self::connect(base_param, write_flash_params, StubKind::Flash);
+        }
+
+        // 先用 NOR 的 stub 查询 flash，根据结果决定是否需要换成 NAND 的 stub
+        base_param.memory_type = "nor".to_string();
+        let mut tool = Self::connect(base_param, write_flash_params, StubKind::Flash)?;
+        let memory_type = tool.detect_memory_type()?;
+        if memory_type == "nor" {
+            return Ok(tool);
+        }
+        let mut base_param = tool.base.clone();
+        let write_flash_params = tool.write_flash_params.take();
+        drop(tool);
+        base_param.memory_type = memory_type;
+        Self::connect(base_param, write_flash_params, StubKind::Flash)
+    }
+
+    /// 下载工厂校准 stub 而不是烧录 stub，之后只能使用 `FactoryCaliTrait` 和 `Reset`
+    pub fn try_new_factory_cali(mut base_param: SifliToolBase) -> Result<Self, std::io::Error> {
+        base_param.port_name = ports::resolve_port(&base_param.port_name)?;
+        Self::connect(base_param, None, StubKind::FactoryCali)
+    }
+
+    fn connect(
+        mut base_param: SifliToolBase,
+        write_flash_params: Option<WriteFlashParams>,
+        stub: StubKind,
+    ) -> Result<Self, std::io::Error> {
+        let (step, chip_id) = Self::download_stub(&mut base_param, stub)?;
         let mut port = serialport::new(&base_param.port_name, 1000000)
             .timeout(Duration::from_secs(5))
-            .open()
-            .unwrap();
+            .open()?;
         // Self::run(&port).unwrap();
         // std::thread::sleep(Duration::from_millis(500));
         let buf: [u8; 14] = [
             0x7E, 0x79, 0x08, 0x00, 0x10, 0x00, 0x41, 0x54, 0x53, 0x46, 0x33, 0x32, 0x18, 0x21,
         ];
         // Turn off the uart debug module again before transferring the data.
-        port.write_all(&buf).unwrap();
-        port.write_all("\r\n".as_bytes()).unwrap();
-        port.flush().unwrap();
-        port.clear(serialport::ClearBuffer::All).unwrap();
+        port.write_all(&buf)?;
+        port.write_all("\r\n".as_bytes())?;
+        port.flush()?;
+        port.clear(serialport::ClearBuffer::All)?;
 
-        Self {
+        Ok(Self {
             port,
             step,
+            chip_id,
+            stub,
             base: base_param,
             write_flash_params,
-        }
+        })
     }
 
     fn run(serial: &Box<dyn SerialPort>) -> Result<(), std::io::Error> {
@@ -197,38 +358,20 @@ impl SifliTool {
         Ok(())
     }
 
-    fn download_stub(base_param: &SifliToolBase) -> Result<i32, std::io::Error> {
-        let spinner = ProgressBar::new_spinner();
+    fn download_stub(
+        base_param: &mut SifliToolBase,
+        kind: StubKind,
+    ) -> Result<(i32, Option<u32>), std::io::Error> {
+        let spinner = base_param.new_spinner();
         let mut step = 0;
 
-        unsafe {
-            env::set_var("SIFLI_UART_DEBUG", "1");
-        }
-
-        let lister = Lister::new();
-        let probes = lister.list_all();
-
-        let index = probes.iter().enumerate().find_map(|(index, probe)| {
-            probe.serial_number.as_ref().and_then(|s| {
-                if s.contains(base_param.port_name.clone().as_str()) {
-                    Some(index)
-                } else {
-                    None
-                }
-            })
-        });
-        let Some(index) = index else {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "No probe found with the given serial number",
-            ));
+        let mut session = if base_param.chip == AUTO_DETECT {
+            chip_info::connect_default_chip(base_param, &mut step)?
+        } else {
+            let probe = ports::open_probe(&base_param.port_name)?;
+            attempt_connect(probe, base_param, &mut step)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
         };
-        let probe = probes[index]
-            .open()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-        let mut session = attempt_connect(probe, base_param, &mut step)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
         if !base_param.quiet {
             spinner.enable_steady_tick(Duration::from_millis(100));
@@ -245,13 +388,22 @@ impl SifliTool {
         core.reset_and_halt(std::time::Duration::from_secs(5))
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
+        // 在 stub 运行前读取芯片 ID 寄存器
+        let chip_id = match chip_info::CHIP_ID_REGISTER.get(base_param.chip.as_str()) {
+            Some(address) => match core.read_word_32(*address) {
+                Ok(id) => Some(id),
+                Err(e) => {
+                    tracing::warn!("Failed to read chip ID: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
         // Download the stub
-        let stub = ram_stub::RamStubFile::get(
-            CHIP_FILE_NAME
-                .get(format!("{}_{}", base_param.chip, base_param.memory_type).as_str())
-                .expect("REASON"),
-        );
-        let Some(stub) = stub else {
+        let image = kind.image(&base_param.chip, &base_param.memory_type);
+        let stub = image.and_then(|(file_name, _)| ram_stub::RamStubFile::get(file_name));
+        let (Some((_, address)), Some(stub)) = (image, stub) else {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 "No stub file found for the given chip and memory type",
@@ -260,16 +412,6 @@ impl SifliTool {
 
         let packet_size = if base_param.compat { 256 } else { 64 * 1024 };
 
-        let mut addr = 0x2005_A000;
-        let mut data = &stub.data[..];
-        while !data.is_empty() {
-            let chunk = &data[..std::cmp::min(data.len(), packet_size)];
-            core.write_8(addr, chunk)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            addr += chunk.len() as u64;
-            data = &data[chunk.len()..];
-        }
-
         let sp = u32::from_le_bytes(
             stub.data[0..4]
                 .try_into()
@@ -280,21 +422,16 @@ impl SifliTool {
                 .try_into()
                 .expect("slice with exactly 4 bytes"),
         );
-        tracing::info!("SP: {:#010x}, PC: {:#010x}", sp, pc);
-        // set SP
-        core.write_core_reg(SP.id, sp)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        // set PC
-        core.write_core_reg(PC.id, pc)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-        core.run()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let segments = [load_ram::RamSegment {
+            address,
+            data: stub.data.into_owned(),
+        }];
+        load_ram::write_and_run(&mut core, &segments, sp, pc, packet_size)?;
         std::thread::sleep(Duration::from_secs(1));
 
         if !base_param.quiet {
             spinner.finish_with_message("Stub download success!");
         }
-        Ok(step)
+        Ok((step, chip_id))
     }
 }
@@ -0,0 +1,896 @@
+//! littlefs v2 镜像的解析和生成，格式参考 littlefs 的 SPEC.md
+
+use crate::read_fs::{ExtractStats, FsType};
+use crate::write_fs::FsImageInfo;
+use crc::Algorithm;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"littlefs";
+
+// 标签类型，见 SPEC.md 中的 "Metadata tags"
+const TYPE_REG: u16 = 0x001;
+const TYPE_DIR: u16 = 0x002;
+const TYPE_SUPERBLOCK: u16 = 0x0FF;
+const TYPE_DIRSTRUCT: u16 = 0x200;
+const TYPE_INLINESTRUCT: u16 = 0x201;
+const TYPE_CTZSTRUCT: u16 = 0x202;
+const TYPE_CREATE: u16 = 0x401;
+const TYPE_DELETE: u16 = 0x4FF;
+const TYPE_SOFTTAIL: u16 = 0x600;
+const TYPE_HARDTAIL: u16 = 0x601;
+const TYPE_CRC: u16 = 0x500;
+// type1 部分
+const TYPE1_NAME: u16 = 0x000;
+const TYPE1_STRUCT: u16 = 0x200;
+const TYPE1_CRC: u16 = 0x500;
+
+const BLOCK_NULL: u32 = 0xFFFF_FFFF;
+const ID_NONE: u16 = 0x3FF;
+
+// 生成的镜像使用 v2.0 磁盘格式，所有 v2 版本的 littlefs 都可以挂载
+const DISK_VERSION: u32 = 0x0002_0000;
+const NAME_MAX: usize = 255;
+const FILE_MAX: u32 = 0x7FFF_FFFF;
+const ATTR_MAX: u32 = 1022;
+// 每个元数据对最多容纳的条目数，超出时拆分到下一个元数据对
+const ENTRIES_MAX: usize = 0xFE;
+
+// littlefs 使用的 CRC-32，初值为 0xFFFFFFFF，不取反
+const LFS_CRC_ALGO: Algorithm<u32> = Algorithm {
+    width: 32,
+    poly: 0x04C11DB7,
+    init: 0xFFFF_FFFF,
+    refin: true,
+    refout: true,
+    xorout: 0,
+    check: 0x340B_C6D9,
+    residue: 0,
+};
+const LFS_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&LFS_CRC_ALGO);
+
+fn invalid(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Tag(u32);
+
+impl Tag {
+    fn new(kind: u16, id: u16, size: usize) -> Self {
+        Self(((kind as u32) << 20) | ((id as u32) << 10) | size as u32)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.0 & 0x8000_0000 == 0
+    }
+
+    fn kind(&self) -> u16 {
+        ((self.0 >> 20) & 0x7FF) as u16
+    }
+
+    fn type1(&self) -> u16 {
+        self.kind() & 0x700
+    }
+
+    fn id(&self) -> u16 {
+        ((self.0 >> 10) & 0x3FF) as u16
+    }
+
+    fn size(&self) -> u32 {
+        self.0 & 0x3FF
+    }
+
+    // 长度为 0x3FF 的标签表示已删除，没有数据
+    fn data_size(&self) -> u32 {
+        if self.size() == 0x3FF { 0 } else { self.size() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Data {
+    None,
+    Dir([u32; 2]),
+    Inline(Vec<u8>),
+    Ctz { head: u32, size: u32 },
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    kind: u16,
+    name: Vec<u8>,
+    data: Data,
+}
+
+impl Entry {
+    fn empty() -> Self {
+        Self {
+            kind: 0,
+            name: Vec::new(),
+            data: Data::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MetadataPair {
+    entries: Vec<Entry>,
+    /// 尾指针及其是否为 hardtail（目录被拆分到下一个元数据对中）
+    tail: Option<([u32; 2], bool)>,
+}
+
+pub struct LittleFs<'a> {
+    image: &'a [u8],
+    pub block_size: u32,
+    pub block_count: u32,
+}
+
+impl<'a> LittleFs<'a> {
+    /// 在镜像中查找超级块，块大小从超级块中读取
+    pub fn mount(image: &'a [u8]) -> Result<Self, std::io::Error> {
+        // 超级块位于块 0 和块 1，块 0 中的副本可能已被擦除，再按常见的块大小查找块 1
+        let starts = std::iter::once((0, image.len()))
+            .chain((9..=18).map(|shift| (1usize << shift, 1usize << shift)));
+        for (start, size) in starts {
+            if image.get(start + 8..start + 16) != Some(&MAGIC[..]) {
+                continue;
+            }
+            let end = std::cmp::min(start + size, image.len());
+            let Ok(pair) = fetch_block(&image[start..end]) else {
+                continue;
+            };
+            let Some(Entry {
+                data: Data::Inline(superblock),
+                ..
+            }) = pair.entries.first()
+            else {
+                continue;
+            };
+            let word = |i: usize| {
+                superblock
+                    .get(i * 4..i * 4 + 4)
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            };
+            let (Some(version), Some(block_size), Some(block_count)) = (word(0), word(1), word(2))
+            else {
+                continue;
+            };
+            if version >> 16 != 2 {
+                return Err(invalid(format!(
+                    "Unsupported littlefs version {}.{}",
+                    version >> 16,
+                    version & 0xFFFF
+                )));
+            }
+            if block_size == 0 || (block_size as u64 * block_count as u64) > image.len() as u64 {
+                return Err(invalid(format!(
+                    "littlefs needs {} blocks of {} bytes, but the image is only {} bytes",
+                    block_count,
+                    block_size,
+                    image.len()
+                )));
+            }
+            return Ok(Self {
+                image,
+                block_size,
+                block_count,
+            });
+        }
+        Err(invalid("No littlefs superblock found"))
+    }
+
+    fn block(&self, block: u32) -> Result<&'a [u8], std::io::Error> {
+        if block >= self.block_count {
+            return Err(invalid(format!("Block {} is out of range", block)));
+        }
+        let start = block as usize * self.block_size as usize;
+        Ok(&self.image[start..start + self.block_size as usize])
+    }
+
+    // 元数据对中版本号较新且包含有效提交的块为当前块
+    fn fetch(&self, pair: [u32; 2]) -> Result<MetadataPair, std::io::Error> {
+        let revision = |block: &[u8]| u32::from_le_bytes(block[..4].try_into().unwrap());
+        let blocks = [self.block(pair[0])?, self.block(pair[1])?];
+        let order = if (revision(blocks[1]).wrapping_sub(revision(blocks[0])) as i32) > 0 {
+            [1, 0]
+        } else {
+            [0, 1]
+        };
+        fetch_block(blocks[order[0]]).or_else(|_| fetch_block(blocks[order[1]]))
+    }
+
+    // 读取目录的所有条目，目录可能通过 hardtail 拆分到多个元数据对中
+    fn read_dir(&self, mut pair: [u32; 2]) -> Result<Vec<Entry>, std::io::Error> {
+        let mut entries = Vec::new();
+        let mut visited = Vec::new();
+        loop {
+            if visited.contains(&pair) {
+                return Err(invalid("Loop in littlefs metadata tails"));
+            }
+            visited.push(pair);
+            let metadata = self.fetch(pair)?;
+            entries.extend(metadata.entries);
+            match metadata.tail {
+                Some((tail, true)) => pair = tail,
+                _ => return Ok(entries),
+            }
+        }
+    }
+
+    fn read_ctz(&self, head: u32, size: u32) -> Result<Vec<u8>, std::io::Error> {
+        let capacity = |index: u32| {
+            let pointers = if index == 0 {
+                0
+            } else {
+                index.trailing_zeros() + 1
+            };
+            (pointers * 4, self.block_size - pointers * 4)
+        };
+        // 每个块的第一个指针指向前一个块，从最后一个块倒序找到所有块
+        let mut last = 0;
+        let mut remaining = size;
+        while remaining > capacity(last).1 {
+            remaining -= capacity(last).1;
+            last += 1;
+        }
+        let mut blocks = vec![head];
+        for _ in 0..last {
+            let block = self.block(*blocks.last().unwrap())?;
+            blocks.push(u32::from_le_bytes(block[..4].try_into().unwrap()));
+        }
+        blocks.reverse();
+
+        let mut data = Vec::with_capacity(size as usize);
+        for (index, block) in blocks.iter().enumerate() {
+            let (offset, len) = capacity(index as u32);
+            let len = std::cmp::min(len, size - data.len() as u32);
+            let block = self.block(*block)?;
+            data.extend_from_slice(&block[offset as usize..(offset + len) as usize]);
+        }
+        Ok(data)
+    }
+
+    /// 将文件系统中的所有文件和目录提取到主机目录 `out`
+    pub fn extract(&self, out: &Path) -> Result<ExtractStats, std::io::Error> {
+        let mut stats = ExtractStats::default();
+        std::fs::create_dir_all(out)?;
+        self.extract_dir([0, 1], out, &mut stats, 0)?;
+        Ok(stats)
+    }
+
+    fn extract_dir(
+        &self,
+        pair: [u32; 2],
+        out: &Path,
+        stats: &mut ExtractStats,
+        depth: usize,
+    ) -> Result<(), std::io::Error> {
+        if depth > 64 {
+            return Err(invalid("littlefs directories are nested too deeply"));
+        }
+        for entry in self.read_dir(pair)? {
+            let name = String::from_utf8_lossy(&entry.name).into_owned();
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                continue;
+            }
+            let path = out.join(&name);
+            match (entry.kind, entry.data) {
+                (TYPE_DIR, Data::Dir(dir)) => {
+                    std::fs::create_dir_all(&path)?;
+                    stats.dirs += 1;
+                    self.extract_dir(dir, &path, stats, depth + 1)?;
+                }
+                (TYPE_REG, data) => {
+                    let data = match data {
+                        Data::Inline(data) => data,
+                        Data::Ctz { head, size } if size > 0 && head != BLOCK_NULL => {
+                            self.read_ctz(head, size)?
+                        }
+                        _ => Vec::new(),
+                    };
+                    std::fs::write(&path, &data)?;
+                    stats.files += 1;
+                    stats.bytes += data.len() as u64;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// 按顺序回放一个块中所有通过 CRC 校验的提交，得到最终的条目和尾指针
+fn fetch_block(block: &[u8]) -> Result<MetadataPair, std::io::Error> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut tail = None;
+    // 当前提交中尚未确认的标签
+    let mut pending: Vec<(Tag, &[u8])> = Vec::new();
+    let mut commits = 0;
+
+    let mut off = 4;
+    let mut commit_start = 0;
+    let mut ptag = 0xFFFF_FFFFu32;
+    while off + 4 <= block.len() {
+        let raw = u32::from_be_bytes(block[off..off + 4].try_into().unwrap());
+        let tag = Tag(raw ^ ptag);
+        if !tag.is_valid() {
+            break;
+        }
+        let data_end = off + 4 + tag.data_size() as usize;
+        if data_end > block.len() {
+            break;
+        }
+        ptag = tag.0;
+
+        if tag.type1() == TYPE1_CRC {
+            if tag.data_size() < 4 {
+                break;
+            }
+            let crc = u32::from_le_bytes(block[off + 4..off + 8].try_into().unwrap());
+            if LFS_CRC.checksum(&block[commit_start..off + 4]) != crc {
+                break;
+            }
+            for (tag, data) in pending.drain(..) {
+                apply(&mut entries, &mut tail, tag, data);
+            }
+            commits += 1;
+            // CRC 标签的 chunk 最低位决定下一个标签的有效位，使擦除后的 0xFF 无效
+            ptag ^= ((tag.kind() & 1) as u32) << 31;
+            commit_start = data_end;
+        } else {
+            pending.push((tag, &block[off + 4..data_end]));
+        }
+        off = data_end;
+    }
+
+    if commits == 0 {
+        return Err(invalid("No valid commit in littlefs metadata block"));
+    }
+    Ok(MetadataPair { entries, tail })
+}
+
+fn apply(entries: &mut Vec<Entry>, tail: &mut Option<([u32; 2], bool)>, tag: Tag, data: &[u8]) {
+    let id = tag.id() as usize;
+    let entry = |entries: &mut Vec<Entry>| -> Option<usize> {
+        if id == 0x3FF {
+            return None;
+        }
+        if id >= entries.len() {
+            entries.resize(id + 1, Entry::empty());
+        }
+        Some(id)
+    };
+    match tag.kind() {
+        TYPE_CREATE => {
+            if id <= entries.len() {
+                entries.insert(id, Entry::empty());
+            }
+        }
+        TYPE_DELETE => {
+            if id < entries.len() {
+                entries.remove(id);
+            }
+        }
+        TYPE_SOFTTAIL | TYPE_HARDTAIL if data.len() >= 8 => {
+            let pair = [
+                u32::from_le_bytes(data[..4].try_into().unwrap()),
+                u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            ];
+            *tail = Some((pair, tag.kind() == TYPE_HARDTAIL));
+        }
+        kind if tag.type1() == TYPE1_NAME && tag.size() != 0x3FF => {
+            if let Some(i) = entry(entries) {
+                entries[i].kind = kind;
+                entries[i].name = data.to_vec();
+            }
+        }
+        kind if tag.type1() == TYPE1_STRUCT && tag.size() != 0x3FF => {
+            let Some(i) = entry(entries) else {
+                return;
+            };
+            let word = |n: usize| {
+                data.get(n * 4..n * 4 + 4)
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            };
+            entries[i].data = match (kind, word(0), word(1)) {
+                (TYPE_DIRSTRUCT, Some(a), Some(b)) => Data::Dir([a, b]),
+                (TYPE_CTZSTRUCT, Some(head), Some(size)) => Data::Ctz { head, size },
+                (TYPE_INLINESTRUCT, _, _) => Data::Inline(data.to_vec()),
+                _ => Data::None,
+            };
+        }
+        _ => {}
+    }
+}
+
+// 主机目录树，目录按深度优先的顺序编号，根目录为 0
+struct HostDir {
+    entries: Vec<(Vec<u8>, HostNode)>,
+    /// 拆分后每个元数据对包含的条目数
+    chunks: Vec<usize>,
+    pairs: Vec<[u32; 2]>,
+}
+
+enum HostNode {
+    File(PathBuf),
+    Dir(usize),
+}
+
+// 一次提交：修订号、依次异或的标签和数据，最后是 CRC 标签。
+// 与 littlefs 整理（compact）后的元数据块一样不写 CREATE 标签，条目编号由名称标签决定，
+// 超级块的 "littlefs" 因此位于块内偏移 8 处
+struct Commit {
+    data: Vec<u8>,
+    ptag: u32,
+}
+
+impl Commit {
+    fn new(revision: u32) -> Self {
+        Self {
+            data: revision.to_le_bytes().to_vec(),
+            ptag: 0xFFFF_FFFF,
+        }
+    }
+
+    fn push(&mut self, kind: u16, id: u16, data: &[u8]) {
+        let tag = Tag::new(kind, id, data.len());
+        self.data.extend((tag.0 ^ self.ptag).to_be_bytes());
+        self.data.extend_from_slice(data);
+        self.ptag = tag.0;
+    }
+
+    // CRC 标签的 chunk 最低位为 0，下一个标签处擦除后的 0xFF 被视为无效
+    fn finish(mut self) -> Vec<u8> {
+        let tag = Tag::new(TYPE_CRC, ID_NONE, 4);
+        self.data.extend((tag.0 ^ self.ptag).to_be_bytes());
+        let crc = LFS_CRC.checksum(&self.data);
+        self.data.extend(crc.to_le_bytes());
+        self.data
+    }
+}
+
+struct Builder {
+    image: Vec<u8>,
+    block_size: u32,
+    block_count: u32,
+    next_block: u32,
+}
+
+impl Builder {
+    fn alloc(&mut self) -> Result<u32, std::io::Error> {
+        if self.next_block >= self.block_count {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "The files do not fit in {} blocks of {} bytes",
+                    self.block_count, self.block_size
+                ),
+            ));
+        }
+        self.next_block += 1;
+        Ok(self.next_block - 1)
+    }
+
+    fn block_mut(&mut self, block: u32) -> &mut [u8] {
+        let start = block as usize * self.block_size as usize;
+        &mut self.image[start..start + self.block_size as usize]
+    }
+
+    // 按 CTZ 跳表写入文件数据，第 n 个块开头保存指向第 n - 2^k 个块的指针
+    fn write_ctz(&mut self, data: &[u8]) -> Result<u32, std::io::Error> {
+        let mut blocks: Vec<u32> = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let index = blocks.len() as u32;
+            let pointers = if index == 0 {
+                0
+            } else {
+                index.trailing_zeros() + 1
+            };
+            let block = self.alloc()?;
+            let len = std::cmp::min((self.block_size - pointers * 4) as usize, data.len() - pos);
+            let skips: Vec<u32> = (0..pointers)
+                .map(|k| blocks[(index - (1 << k)) as usize])
+                .collect();
+            let buffer = self.block_mut(block);
+            for (k, skip) in skips.iter().enumerate() {
+                buffer[k * 4..k * 4 + 4].copy_from_slice(&skip.to_le_bytes());
+            }
+            let offset = pointers as usize * 4;
+            buffer[offset..offset + len].copy_from_slice(&data[pos..pos + len]);
+            blocks.push(block);
+            pos += len;
+        }
+        Ok(*blocks.last().unwrap())
+    }
+}
+
+fn scan_dir(source: &Path, dirs: &mut Vec<HostDir>) -> Result<usize, std::io::Error> {
+    let index = dirs.len();
+    dirs.push(HostDir {
+        entries: Vec::new(),
+        chunks: Vec::new(),
+        pairs: Vec::new(),
+    });
+    let mut entries = std::fs::read_dir(source)?.collect::<Result<Vec<_>, _>>()?;
+    // littlefs 中目录条目按名称的字节序排列
+    entries.sort_by_key(|entry| entry.file_name());
+    let mut result = Vec::new();
+    for entry in entries {
+        let Some(name) = entry.file_name().to_str().map(|n| n.as_bytes().to_vec()) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid file name: {:?}", entry.path()),
+            ));
+        };
+        if name.len() > NAME_MAX {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "File name is longer than {} bytes: {:?}",
+                    NAME_MAX,
+                    entry.path()
+                ),
+            ));
+        }
+        let metadata = std::fs::metadata(entry.path())?;
+        if metadata.is_dir() {
+            result.push((name, HostNode::Dir(scan_dir(&entry.path(), dirs)?)));
+        } else if metadata.is_file() {
+            if metadata.len() > FILE_MAX as u64 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("File is too large: {:?}", entry.path()),
+                ));
+            }
+            result.push((name, HostNode::File(entry.path())));
+        }
+    }
+    dirs[index].entries = result;
+    Ok(index)
+}
+
+// 将目录条目拆分到多个元数据对中，每个元数据对的内容不超过块大小的一半，
+// 与 littlefs 整理元数据时的限制一致
+fn split_entries(entries: &[(Vec<u8>, HostNode)], block_size: u32, root: bool) -> Vec<usize> {
+    // 修订号、尾指针和 CRC 标签
+    let overhead = 4 + 12 + 8;
+    // 超级块的名称和结构标签
+    let superblock = (4 + MAGIC.len()) + (4 + 24);
+    let budget = block_size as usize / 2;
+
+    let mut chunks = Vec::new();
+    let mut used = overhead + if root { superblock } else { 0 };
+    let mut count = if root { 1 } else { 0 };
+    let mut first = 0;
+    for (i, (name, _)) in entries.iter().enumerate() {
+        // 名称和结构标签
+        let size = (4 + name.len()) + (4 + 8);
+        if i > first && (used + size > budget || count >= ENTRIES_MAX) {
+            chunks.push(i - first);
+            first = i;
+            used = overhead;
+            count = 0;
+        }
+        used += size;
+        count += 1;
+    }
+    chunks.push(entries.len() - first);
+    chunks
+}
+
+/// 用主机目录中的文件生成 littlefs 镜像，未使用的块保持擦除状态（0xFF）。
+/// 非空文件全部以 CTZ 跳表保存，不使用内联文件，
+/// 避免内联数据超过设备上配置的 cache_size
+pub fn build_littlefs_image(
+    source: &Path,
+    block_size: u32,
+    block_count: u32,
+) -> Result<(Vec<u8>, FsImageInfo), std::io::Error> {
+    if !source.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} is not a directory", source.display()),
+        ));
+    }
+    if block_size < 128 || block_size % 4 != 0 || block_count < 2 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "littlefs needs at least 2 blocks of 128 bytes or more",
+        ));
+    }
+
+    let mut dirs = Vec::new();
+    scan_dir(source, &mut dirs)?;
+
+    let mut builder = Builder {
+        image: vec![0xFF; block_size as usize * block_count as usize],
+        block_size,
+        block_count,
+        next_block: 0,
+    };
+    // 先分配所有元数据对，根目录固定在块 0 和块 1
+    for (index, dir) in dirs.iter_mut().enumerate() {
+        dir.chunks = split_entries(&dir.entries, block_size, index == 0);
+        for _ in 0..dir.chunks.len() {
+            dir.pairs.push([builder.alloc()?, builder.alloc()?]);
+        }
+    }
+
+    let mut info = FsImageInfo {
+        fs_type: FsType::LittleFs.to_string(),
+        block_size,
+        size: block_size * block_count,
+        files: 0,
+        dirs: dirs.len() - 1,
+        bytes: 0,
+        used: 0,
+    };
+
+    // 所有元数据对通过尾指针串成一个链表，littlefs 分配块时依靠它找到已使用的块。
+    // 同一目录拆分出的元数据对之间用 hardtail，目录之间用 softtail
+    let pairs: Vec<(usize, usize)> = dirs
+        .iter()
+        .enumerate()
+        .flat_map(|(index, dir)| (0..dir.chunks.len()).map(move |chunk| (index, chunk)))
+        .collect();
+    for (position, &(index, chunk)) in pairs.iter().enumerate() {
+        let mut commit = Commit::new(1);
+        let mut id = 0u16;
+        if index == 0 && chunk == 0 {
+            let mut superblock = Vec::new();
+            for word in [
+                DISK_VERSION,
+                block_size,
+                block_count,
+                NAME_MAX as u32,
+                FILE_MAX,
+                ATTR_MAX,
+            ] {
+                superblock.extend(word.to_le_bytes());
+            }
+            commit.push(TYPE_SUPERBLOCK, 0, MAGIC);
+            commit.push(TYPE_INLINESTRUCT, 0, &superblock);
+            id += 1;
+        }
+
+        let first: usize = dirs[index].chunks[..chunk].iter().sum();
+        let count = dirs[index].chunks[chunk];
+        for i in first..first + count {
+            let (name, node) = &dirs[index].entries[i];
+            match node {
+                HostNode::Dir(sub) => {
+                    let pair = dirs[*sub].pairs[0];
+                    let mut data = pair[0].to_le_bytes().to_vec();
+                    data.extend(pair[1].to_le_bytes());
+                    commit.push(TYPE_DIR, id, name);
+                    commit.push(TYPE_DIRSTRUCT, id, &data);
+                }
+                HostNode::File(path) => {
+                    let data = std::fs::read(path)?;
+                    commit.push(TYPE_REG, id, name);
+                    if data.is_empty() {
+                        // 与 littlefs 创建空文件时一样，使用长度为 0 的内联结构
+                        commit.push(TYPE_INLINESTRUCT, id, &[]);
+                    } else {
+                        let head = builder.write_ctz(&data)?;
+                        let mut ctz = head.to_le_bytes().to_vec();
+                        ctz.extend((data.len() as u32).to_le_bytes());
+                        commit.push(TYPE_CTZSTRUCT, id, &ctz);
+                    }
+                    info.files += 1;
+                    info.bytes += data.len() as u64;
+                }
+            }
+            id += 1;
+        }
+
+        if let Some(&(next_index, next_chunk)) = pairs.get(position + 1) {
+            let next = dirs[next_index].pairs[next_chunk];
+            let mut data = next[0].to_le_bytes().to_vec();
+            data.extend(next[1].to_le_bytes());
+            let kind = if next_index == index {
+                TYPE_HARDTAIL
+            } else {
+                TYPE_SOFTTAIL
+            };
+            commit.push(kind, ID_NONE, &data);
+        }
+
+        let data = commit.finish();
+        if data.len() > block_size as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Directory entries do not fit in a littlefs metadata block",
+            ));
+        }
+        // 元数据对的第二个块保持擦除状态，修订号 0xFFFFFFFF 会被视为较旧的块
+        let block = dirs[index].pairs[chunk][0];
+        builder.block_mut(block)[..data.len()].copy_from_slice(&data);
+    }
+
+    info.used = builder.next_block * block_size;
+    Ok((builder.image, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    // 比较两个目录树中的文件内容
+    fn assert_same_tree(expected: &Path, actual: &Path) {
+        let mut names = std::fs::read_dir(expected)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        let mut extracted = std::fs::read_dir(actual)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        extracted.sort();
+        assert_eq!(names, extracted, "{}", actual.display());
+        for name in names {
+            let (expected, actual) = (expected.join(&name), actual.join(&name));
+            if expected.is_dir() {
+                assert_same_tree(&expected, &actual);
+            } else {
+                assert_eq!(
+                    std::fs::read(&expected).unwrap(),
+                    std::fs::read(&actual).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn build_and_extract_round_trip() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("empty"), b"").unwrap();
+        // 跨越多个 CTZ 块，包含多级跳表指针
+        std::fs::write(source.path().join("big.bin"), pattern(20000, 0x5A)).unwrap();
+        std::fs::create_dir_all(source.path().join("a/b")).unwrap();
+        std::fs::write(source.path().join("a/b/deep.txt"), b"deep").unwrap();
+        // 条目足够多，根目录需要通过 hardtail 拆分到多个元数据对
+        for i in 0..40 {
+            std::fs::write(
+                source.path().join(format!("file{:02}.dat", i)),
+                pattern(i * 37 + 1, i as u8),
+            )
+            .unwrap();
+        }
+
+        let (image, info) = build_littlefs_image(source.path(), 512, 256).unwrap();
+        assert_eq!(image.len(), 512 * 256);
+        assert_eq!((info.files, info.dirs), (43, 2));
+        assert_eq!(info.bytes, 20004 + (0..40).map(|i| i * 37 + 1).sum::<u64>());
+        assert!(image[info.used as usize..].iter().all(|b| *b == 0xFF));
+
+        let fs = LittleFs::mount(&image).unwrap();
+        assert_eq!((fs.block_size, fs.block_count), (512, 256));
+        assert!(matches!(fs.fetch([0, 1]).unwrap().tail, Some((_, true))));
+        let out = tempfile::tempdir().unwrap();
+        let stats = fs.extract(out.path()).unwrap();
+        assert_eq!((stats.files, stats.dirs, stats.bytes), (43, 2, info.bytes));
+        assert_same_tree(source.path(), out.path());
+    }
+
+    #[test]
+    fn build_rejects_files_that_do_not_fit() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("big.bin"), pattern(4096, 0)).unwrap();
+        assert!(build_littlefs_image(source.path(), 512, 8).is_err());
+        assert!(build_littlefs_image(source.path(), 100, 64).is_err());
+    }
+
+    // 按 SPEC.md 手工拼出的元数据块，与 littlefs 自身的写法相同：
+    // 带 CREATE/DELETE 标签、一个块中有多次提交、CRC 标签带填充
+    struct SpecBlock {
+        data: Vec<u8>,
+        ptag: u32,
+        commit: usize,
+    }
+
+    impl SpecBlock {
+        fn new(revision: u32) -> Self {
+            Self {
+                data: revision.to_le_bytes().to_vec(),
+                ptag: 0xFFFF_FFFF,
+                commit: 0,
+            }
+        }
+
+        fn tag(&mut self, kind: u32, id: u32, data: &[u8]) -> &mut Self {
+            let tag = (kind << 20) | (id << 10) | data.len() as u32;
+            self.data.extend((tag ^ self.ptag).to_be_bytes());
+            self.data.extend_from_slice(data);
+            self.ptag = tag;
+            self
+        }
+
+        fn crc(&mut self, padding: usize) -> &mut Self {
+            let tag = (0x500 << 20) | (0x3FF << 10) | (4 + padding) as u32;
+            self.data.extend((tag ^ self.ptag).to_be_bytes());
+            let crc = crc::Crc::<u32>::new(&crc::CRC_32_JAMCRC).checksum(&self.data[self.commit..]);
+            self.data.extend(crc.to_le_bytes());
+            self.data.extend(std::iter::repeat_n(0xFF, padding));
+            self.ptag = tag;
+            self.commit = self.data.len();
+            self
+        }
+    }
+
+    #[test]
+    fn mount_image_written_like_littlefs() {
+        let block_size = 512usize;
+        let mut image = vec![0xFFu8; block_size * 8];
+        let mut superblock = Vec::new();
+        for word in [0x0002_0001u32, 512, 8, 255, 0x7FFF_FFFF, 1022] {
+            superblock.extend(word.to_le_bytes());
+        }
+        let log = pattern(700, 7);
+        let mut put = |block: usize, data: &[u8]| {
+            image[block * block_size..block * block_size + data.len()].copy_from_slice(data)
+        };
+
+        // 块 0 是格式化时第一次提交的旧版本，块 1 是整理后的新版本，之后追加了多次提交
+        let mut old = SpecBlock::new(1);
+        old.tag(0x401, 0, &[])
+            .tag(0x0FF, 0, MAGIC)
+            .tag(0x201, 0, &superblock)
+            .crc(0);
+        put(0, &old.data);
+
+        let mut root = SpecBlock::new(2);
+        root.tag(0x0FF, 0, MAGIC).tag(0x201, 0, &superblock).crc(3);
+        root.tag(0x401, 1, &[])
+            .tag(0x001, 1, b"hello.txt")
+            .tag(0x201, 1, b"Hello, littlefs!")
+            .crc(0);
+        // 插入到 hello.txt 之前，随后删除
+        root.tag(0x401, 1, &[])
+            .tag(0x001, 1, b"gone.txt")
+            .tag(0x201, 1, b"x")
+            .crc(0);
+        root.tag(0x4FF, 1, &[]).crc(1);
+        let mut pair = 2u32.to_le_bytes().to_vec();
+        pair.extend(3u32.to_le_bytes());
+        root.tag(0x401, 2, &[])
+            .tag(0x002, 2, b"logs")
+            .tag(0x200, 2, &pair)
+            .tag(0x600, 0x3FF, &pair)
+            .crc(0);
+        put(1, &root.data);
+
+        // 700 字节的文件占用两个 CTZ 块：块 4 保存前 512 字节，块 5 以指向块 4 的指针开头
+        let mut ctz = 5u32.to_le_bytes().to_vec();
+        ctz.extend(700u32.to_le_bytes());
+        let mut logs = SpecBlock::new(1);
+        logs.tag(0x401, 0, &[])
+            .tag(0x001, 0, b"a.log")
+            .tag(0x202, 0, &ctz)
+            .crc(0);
+        put(2, &logs.data);
+        put(4, &log[..512]);
+        let mut tail = 4u32.to_le_bytes().to_vec();
+        tail.extend_from_slice(&log[512..]);
+        put(5, &tail);
+
+        let fs = LittleFs::mount(&image).unwrap();
+        assert_eq!((fs.block_size, fs.block_count), (512, 8));
+        let out = tempfile::tempdir().unwrap();
+        let stats = fs.extract(out.path()).unwrap();
+        assert_eq!((stats.files, stats.dirs, stats.bytes), (2, 1, 16 + 700));
+        assert_eq!(
+            std::fs::read(out.path().join("hello.txt")).unwrap(),
+            b"Hello, littlefs!"
+        );
+        assert_eq!(std::fs::read(out.path().join("logs/a.log")).unwrap(), log);
+        assert!(!out.path().join("gone.txt").exists());
+    }
+}
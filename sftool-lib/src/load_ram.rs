@@ -0,0 +1,350 @@
+use crate::SifliDebugTool;
+use crate::write_flash::{FileType, detect_file_type, str_to_u32};
+use indicatif::ProgressStyle;
+use memmap2::Mmap;
+use phf::phf_map;
+use probe_rs::architecture::arm::core::registers::cortex_m::{PC, SP};
+use probe_rs::{Core, MemoryInterface};
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+// SCB->VTOR，加载用户镜像后指向镜像自己的中断向量表
+const VTOR: u64 = 0xE000_ED08;
+
+// 启动文件中常见的中断向量表符号，SiFli SDK 和 CMSIS 的启动文件使用 `__Vectors`
+const VECTOR_TABLE_SYMBOLS: &[&str] =
+    &["__Vectors", "__isr_vector", "g_pfnVectors", "__vector_table"];
+
+// 复位后不需要初始化就能直接写入的 RAM，`[起始地址, 结束地址)`
+static CHIP_RAM_RANGES: phf::Map<&'static str, &'static [(u64, u64)]> = phf_map! {
+    "sf32lb52" => &[(0x2000_0000, 0x2008_0000)],
+};
+
+#[derive(Clone)]
+pub struct LoadRamParams {
+    /// `FILE@ADDRESS` 形式，ELF 文件可以省略地址
+    pub file_path: String,
+}
+
+pub trait LoadRamTrait {
+    fn load_ram(&mut self, params: &LoadRamParams) -> Result<(), std::io::Error>;
+}
+
+/// 加载到 RAM 中的一段数据
+pub(crate) struct RamSegment {
+    pub address: u64,
+    pub data: Vec<u8>,
+}
+
+/// 准备好运行的 RAM 镜像
+pub(crate) struct RamImage {
+    pub segments: Vec<RamSegment>,
+    /// 中断向量表地址，同时也是 bin 文件的加载地址
+    pub vector_table: u64,
+    pub sp: u32,
+    pub pc: u32,
+}
+
+/// 将各个段写入 RAM，设置 SP/PC 后让内核开始运行，调用前内核必须处于暂停状态
+pub(crate) fn write_and_run(
+    core: &mut Core,
+    segments: &[RamSegment],
+    sp: u32,
+    pc: u32,
+    packet_size: usize,
+) -> Result<(), std::io::Error> {
+    for segment in segments.iter() {
+        let mut addr = segment.address;
+        let mut data = &segment.data[..];
+        while !data.is_empty() {
+            let chunk = &data[..std::cmp::min(data.len(), packet_size)];
+            core.write_8(addr, chunk)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            addr += chunk.len() as u64;
+            data = &data[chunk.len()..];
+        }
+    }
+
+    tracing::info!("SP: {:#010x}, PC: {:#010x}", sp, pc);
+    // set SP
+    core.write_core_reg(SP.id, sp)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    // set PC
+    core.write_core_reg(PC.id, pc)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    core.run()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+}
+
+// bin 文件的开头就是中断向量表，第一个字是 SP，第二个字是复位向量
+fn vector_table_entries(data: &[u8]) -> Result<(u32, u32), std::io::Error> {
+    if data.len() < 8 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Image is too small to contain a vector table",
+        ));
+    }
+    let sp = u32::from_le_bytes(data[0..4].try_into().expect("slice with exactly 4 bytes"));
+    let pc = u32::from_le_bytes(data[4..8].try_into().expect("slice with exactly 4 bytes"));
+    Ok((sp, pc))
+}
+
+// 在已加载的段中读取 `address` 处中断向量表的前两个字
+fn vector_table_at(segments: &[RamSegment], address: u64) -> Option<(u32, u32)> {
+    let segment = segments
+        .iter()
+        .find(|s| s.address <= address && address + 8 <= s.address + s.data.len() as u64)?;
+    vector_table_entries(&segment.data[(address - segment.address) as usize..]).ok()
+}
+
+/// 确定 ELF 镜像的中断向量表地址和初始 SP。
+///
+/// 优先使用向量表符号；没有符号时取入口地址所在段的开头，并要求其中的复位向量
+/// 指向入口地址，避免把地址最低的段中的普通数据当作 SP。
+fn elf_vector_table(
+    segments: &[RamSegment],
+    symbol: Option<u64>,
+    entry: u32,
+) -> Result<(u64, u32), std::io::Error> {
+    if let Some(address) = symbol {
+        let (sp, _) = vector_table_at(segments, address).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Vector table 0x{:08X} is not inside a loadable segment", address),
+            )
+        })?;
+        return Ok((address, sp));
+    }
+
+    let entry = entry as u64 & !1;
+    segments
+        .iter()
+        .filter(|s| s.address <= entry && entry < s.address + s.data.len() as u64)
+        .find_map(|s| match vector_table_entries(&s.data) {
+            Ok((sp, reset)) if reset as u64 & !1 == entry => Some((s.address, sp)),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Unable to locate the vector table: no vector table symbol, and the segment containing the entry point 0x{:08X} does not start with a reset vector pointing to it",
+                    entry
+                ),
+            )
+        })
+}
+
+fn bin_image(path: &Path, address: u32) -> Result<RamImage, std::io::Error> {
+    let data = std::fs::read(path)?;
+    let (sp, pc) = vector_table_entries(&data)?;
+    Ok(RamImage {
+        segments: vec![RamSegment {
+            address: address as u64,
+            data,
+        }],
+        vector_table: address as u64,
+        sp,
+        pc,
+    })
+}
+
+fn elf_image(path: &Path) -> Result<RamImage, std::io::Error> {
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let elf = goblin::elf::Elf::parse(&mmap[..])
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let mut load_segments: Vec<_> = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == goblin::elf::program_header::PT_LOAD && ph.p_filesz > 0)
+        .collect();
+    load_segments.sort_by_key(|ph| ph.p_paddr);
+    if load_segments.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "ELF file has no loadable segments",
+        ));
+    }
+
+    let mut segments = Vec::new();
+    for ph in load_segments.iter() {
+        let offset = ph.p_offset as usize;
+        let size = ph.p_filesz as usize;
+        let data = mmap.get(offset..offset + size).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "ELF segment out of range")
+        })?;
+        segments.push(RamSegment {
+            address: ph.p_paddr,
+            data: data.to_vec(),
+        });
+    }
+
+    // SP 取自中断向量表的第一个字，PC 取 ELF 入口地址
+    let symbol = elf.syms.iter().find_map(|sym| {
+        let name = elf.strtab.get_at(sym.st_name)?;
+        VECTOR_TABLE_SYMBOLS.contains(&name).then_some(sym.st_value)
+    });
+    let (vector_table, sp) = elf_vector_table(&segments, symbol, elf.entry as u32)?;
+    Ok(RamImage {
+        vector_table,
+        segments,
+        sp,
+        pc: elf.entry as u32,
+    })
+}
+
+// 每一段都必须完整地落在芯片的某个 RAM 范围内，否则会写到 flash 或外设寄存器上
+fn check_ram_ranges(chip: &str, segments: &[RamSegment]) -> Result<(), std::io::Error> {
+    let Some(ranges) = CHIP_RAM_RANGES.get(chip) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unknown RAM layout for {}", chip),
+        ));
+    };
+    for segment in segments.iter() {
+        let start = segment.address;
+        let end = start + segment.data.len() as u64;
+        if ranges.iter().any(|(low, high)| *low <= start && end <= *high) {
+            continue;
+        }
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|(low, high)| format!("0x{:08X} - 0x{:08X}", low, high - 1))
+            .collect();
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Segment 0x{:08X} - 0x{:08X} is outside the RAM of {} ({})",
+                start,
+                end - 1,
+                chip.to_uppercase(),
+                ranges.join(", ")
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// 解析 `FILE@ADDRESS`，带地址时按 bin 文件加载，否则必须是 ELF 文件
+pub(crate) fn parse_ram_image(file_path: &str, chip: &str) -> Result<RamImage, std::io::Error> {
+    let parts: Vec<_> = file_path.split('@').collect();
+    let image = if parts.len() == 2 {
+        let address = str_to_u32(parts[1])
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        bin_image(Path::new(parts[0]), address)?
+    } else {
+        match detect_file_type(Path::new(parts[0]))? {
+            FileType::Elf => elf_image(Path::new(parts[0]))?,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "For binary files, please use the <file@address> format",
+                ));
+            }
+        }
+    };
+    check_ram_ranges(chip, &image.segments)?;
+    Ok(image)
+}
+
+impl LoadRamTrait for SifliDebugTool {
+    fn load_ram(&mut self, params: &LoadRamParams) -> Result<(), std::io::Error> {
+        let image = parse_ram_image(&params.file_path, &self.base.chip)?;
+
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+            spinner.set_message(format!("Loading {} to RAM...", params.file_path));
+        }
+
+        let packet_size = self.packet_size();
+        let mut core = self.core()?;
+        core.reset_and_halt(Duration::from_secs(5))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        core.write_word_32(VTOR, image.vector_table as u32)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        write_and_run(&mut core, &image.segments, image.sp, image.pc, packet_size)?;
+
+        if !self.base.quiet {
+            spinner.finish_with_message(format!(
+                "Running from RAM (SP 0x{:08X}, PC 0x{:08X})",
+                image.sp, image.pc
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(address: u64, len: usize) -> RamSegment {
+        RamSegment {
+            address,
+            data: vec![0; len],
+        }
+    }
+
+    // 以 `sp` 和 `reset` 开头的段，后面补 0
+    fn vector_segment(address: u64, sp: u32, reset: u32, len: usize) -> RamSegment {
+        let mut data = [sp.to_le_bytes(), reset.to_le_bytes()].concat();
+        data.resize(len, 0);
+        RamSegment { address, data }
+    }
+
+    #[test]
+    fn elf_vector_table_from_symbol() {
+        let segments = [
+            segment(0x2000_0000, 0x100),
+            vector_segment(0x2000_1000, 0x2008_0000, 0x2000_1101, 0x200),
+        ];
+        assert_eq!(
+            elf_vector_table(&segments, Some(0x2000_1000), 0x2000_1101).unwrap(),
+            (0x2000_1000, 0x2008_0000)
+        );
+        // 符号指向没有加载的地址
+        assert!(elf_vector_table(&segments, Some(0x2000_4000), 0x2000_1101).is_err());
+    }
+
+    #[test]
+    fn elf_vector_table_from_entry_segment() {
+        // 地址最低的段是数据，不能把它的第一个字当作 SP
+        let segments = [
+            vector_segment(0x2000_0000, 0x1234_5678, 0, 0x100),
+            vector_segment(0x2000_1000, 0x2008_0000, 0x2000_1101, 0x200),
+        ];
+        assert_eq!(
+            elf_vector_table(&segments, None, 0x2000_1101).unwrap(),
+            (0x2000_1000, 0x2008_0000)
+        );
+        // 入口所在段开头的复位向量不指向入口
+        assert!(elf_vector_table(&segments, None, 0x2000_1181).is_err());
+        // 入口不在任何段中
+        assert!(elf_vector_table(&segments, None, 0x2000_8001).is_err());
+    }
+
+    #[test]
+    fn segments_inside_ram() {
+        let segments = [segment(0x2000_0000, 0x100), segment(0x2007_FF00, 0x100)];
+        assert!(check_ram_ranges("sf32lb52", &segments).is_ok());
+    }
+
+    #[test]
+    fn segments_outside_ram() {
+        // flash 中的段
+        let err = check_ram_ranges("sf32lb52", &[segment(0x1200_0000, 0x100)]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("0x12000000 - 0x120000FF"));
+        // 跨过 RAM 末尾
+        assert!(check_ram_ranges("sf32lb52", &[segment(0x2007_FF00, 0x101)]).is_err());
+        assert!(check_ram_ranges("sf32lb56", &[segment(0x2000_0000, 0x100)]).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::SifliDebugTool;
+use probe_rs::MemoryInterface;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryWidth {
+    Byte,
+    HalfWord,
+    Word,
+}
+
+impl MemoryWidth {
+    pub fn bytes(self) -> usize {
+        match self {
+            MemoryWidth::Byte => 1,
+            MemoryWidth::HalfWord => 2,
+            MemoryWidth::Word => 4,
+        }
+    }
+}
+
+pub trait MemoryTrait {
+    /// 从 `address` 开始读取 `count` 个 `width` 宽度的数据
+    fn read_mem(
+        &mut self,
+        address: u64,
+        count: usize,
+        width: MemoryWidth,
+    ) -> Result<Vec<u32>, std::io::Error>;
+    fn write_mem(&mut self, address: u64, value: u32, width: MemoryWidth)
+    -> Result<(), std::io::Error>;
+}
+
+fn check_alignment(address: u64, width: MemoryWidth) -> Result<(), std::io::Error> {
+    if address % width.bytes() as u64 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Address 0x{:08X} is not aligned to {} bytes",
+                address,
+                width.bytes()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn check_value(value: u32, width: MemoryWidth) -> Result<(), std::io::Error> {
+    let max = match width {
+        MemoryWidth::Byte => u8::MAX as u32,
+        MemoryWidth::HalfWord => u16::MAX as u32,
+        MemoryWidth::Word => u32::MAX,
+    };
+    if value > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Value 0x{:X} does not fit in {} bytes", value, width.bytes()),
+        ));
+    }
+    Ok(())
+}
+
+impl MemoryTrait for SifliDebugTool {
+    fn read_mem(
+        &mut self,
+        address: u64,
+        count: usize,
+        width: MemoryWidth,
+    ) -> Result<Vec<u32>, std::io::Error> {
+        check_alignment(address, width)?;
+        // 按 packet_size 分块读取，避免单次传输过大导致超时
+        let items_per_packet = self.packet_size() / width.bytes();
+        let mut core = self.core()?;
+        let mut values = Vec::with_capacity(count);
+        let mut addr = address;
+        while values.len() < count {
+            let n = std::cmp::min(count - values.len(), items_per_packet);
+            let res = match width {
+                MemoryWidth::Byte => {
+                    let mut data = vec![0u8; n];
+                    core.read_8(addr, &mut data)
+                        .map(|_| values.extend(data.into_iter().map(u32::from)))
+                }
+                MemoryWidth::HalfWord => {
+                    let mut data = vec![0u16; n];
+                    core.read_16(addr, &mut data)
+                        .map(|_| values.extend(data.into_iter().map(u32::from)))
+                }
+                MemoryWidth::Word => {
+                    let mut data = vec![0u32; n];
+                    core.read_32(addr, &mut data).map(|_| values.extend(data))
+                }
+            };
+            res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            addr += (n * width.bytes()) as u64;
+        }
+        Ok(values)
+    }
+
+    fn write_mem(
+        &mut self,
+        address: u64,
+        value: u32,
+        width: MemoryWidth,
+    ) -> Result<(), std::io::Error> {
+        check_alignment(address, width)?;
+        check_value(value, width)?;
+
+        let mut core = self.core()?;
+        match width {
+            MemoryWidth::Byte => core.write_word_8(address, value as u8),
+            MemoryWidth::HalfWord => core.write_word_16(address, value as u16),
+            MemoryWidth::Word => core.write_word_32(address, value),
+        }
+        .and_then(|_| core.flush())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+    }
+}
+
+/// 将 `read_mem` 读到的数据格式化为每行 16 字节的十六进制转储，行尾附带 ASCII
+pub fn format_hexdump(address: u64, width: MemoryWidth, values: &[u32]) -> String {
+    let per_line = 16 / width.bytes();
+    let mut out = String::new();
+    for (line, chunk) in values.chunks(per_line).enumerate() {
+        out.push_str(&format!("0x{:08X}:", address + (line * 16) as u64));
+        let mut ascii = String::new();
+        for value in chunk.iter() {
+            out.push_str(&format!(" {:0width$X}", value, width = width.bytes() * 2));
+            for byte in value.to_le_bytes().iter().take(width.bytes()) {
+                ascii.push(if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                });
+            }
+        }
+        // 最后一行不足时补齐，保证 ASCII 列对齐
+        let missing = per_line - chunk.len();
+        out.push_str(&" ".repeat(missing * (width.bytes() * 2 + 1)));
+        out.push_str(&format!("  |{}|\n", ascii));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alignment_and_value_checks() {
+        assert!(check_alignment(0x2000_0000, MemoryWidth::Word).is_ok());
+        assert!(check_alignment(0x2000_0002, MemoryWidth::HalfWord).is_ok());
+        assert!(check_alignment(0x2000_0002, MemoryWidth::Word).is_err());
+        assert!(check_alignment(0x2000_0001, MemoryWidth::HalfWord).is_err());
+        assert!(check_alignment(0x2000_0001, MemoryWidth::Byte).is_ok());
+
+        assert!(check_value(0xFF, MemoryWidth::Byte).is_ok());
+        assert!(check_value(0x100, MemoryWidth::Byte).is_err());
+        assert!(check_value(0xFFFF, MemoryWidth::HalfWord).is_ok());
+        assert!(check_value(0x1_0000, MemoryWidth::HalfWord).is_err());
+        assert!(check_value(u32::MAX, MemoryWidth::Word).is_ok());
+    }
+
+    #[test]
+    fn hexdump_words() {
+        let values = [0x6C6C_6548, 0x6F77_206F, 0x0A64_6C72, 0x0000_0000, 0xDEAD_BEEF];
+        assert_eq!(
+            format_hexdump(0x2000_0000, MemoryWidth::Word, &values),
+            "0x20000000: 6C6C6548 6F77206F 0A646C72 00000000  |Hello world.....|\n\
+             0x20000010: DEADBEEF                             |....|\n"
+        );
+    }
+
+    #[test]
+    fn hexdump_bytes_and_halfwords() {
+        assert_eq!(
+            format_hexdump(0x1000, MemoryWidth::Byte, &[0x41, 0x20, 0x7F]),
+            format!("0x00001000: 41 20 7F{}  |A .|\n", " ".repeat(13 * 3))
+        );
+        assert_eq!(
+            format_hexdump(0x1000, MemoryWidth::HalfWord, &[0x4241, 0x0001]),
+            format!("0x00001000: 4241 0001{}  |AB..|\n", " ".repeat(6 * 5))
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use crate::SifliTool;
+use crate::symbolize::Symbolizer;
+use console::{Key, Term};
+use serialport::SerialPort;
+use std::io::{BufRead, Write};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// Ctrl+R 重新烧录，Ctrl+] 退出（与 telnet 相同）
+const KEY_REFLASH: char = '\x12';
+const KEY_QUIT: char = '\x1d';
+
+#[derive(Clone)]
+pub struct MonitorParams {
+    /// 应用程序使用的波特率
+    pub baud: u32,
+    /// 在每行开头显示自进入监视器以来的时间
+    pub timestamps: bool,
+    /// 是否响应 Ctrl+R，只有烧录后进入的监视器才能重新烧录
+    pub reflash: bool,
+    /// 固件的 ELF 文件，用于把日志中的地址解析为函数和源码位置
+    pub elf_path: Option<String>,
+}
+
+/// 退出监视器的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorExit {
+    Quit,
+    /// 用户请求复位并重新烧录
+    Reflash,
+}
+
+pub trait MonitorTrait {
+    /// 复用下载时打开的串口，切换到应用程序的波特率后进入监视器
+    fn monitor(&mut self, params: &MonitorParams) -> Result<MonitorExit, std::io::Error>;
+}
+
+enum Input {
+    Line(String),
+    Exit(MonitorExit),
+}
+
+impl MonitorTrait for SifliTool {
+    fn monitor(&mut self, params: &MonitorParams) -> Result<MonitorExit, std::io::Error> {
+        self.port.set_baud_rate(params.baud)?;
+        run_monitor(self.port.as_mut(), params)
+    }
+}
+
+/// 直接打开串口进入监视器，不下载 stub 也不复位芯片
+pub fn monitor_port(port_name: &str, params: &MonitorParams) -> Result<MonitorExit, std::io::Error> {
+    let port_name = crate::ports::resolve_port(port_name)?;
+    let mut port = serialport::new(&port_name, params.baud)
+        .timeout(Duration::from_secs(5))
+        .open()?;
+    run_monitor(port.as_mut(), params)
+}
+
+// 在单独的线程中读取按键，按行发送给主循环。按下热键后线程退出，
+// 避免重新进入监视器时出现两个线程同时读取终端
+fn spawn_input_thread(reflash: bool) -> mpsc::Receiver<Input> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let term = Term::stdout();
+        if !term.is_term() {
+            // 标准输入不是终端（例如被重定向）时逐行读取，没有热键
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if tx.send(Input::Line(line)).is_err() {
+                    break;
+                }
+            }
+            return;
+        }
+
+        let mut line = String::new();
+        loop {
+            let input = match term.read_key_raw() {
+                Ok(Key::Char(KEY_QUIT)) | Ok(Key::CtrlC) | Err(_) => Input::Exit(MonitorExit::Quit),
+                Ok(Key::Char(KEY_REFLASH)) if reflash => Input::Exit(MonitorExit::Reflash),
+                Ok(Key::Enter) => {
+                    let _ = term.write_str("\r\n");
+                    Input::Line(std::mem::take(&mut line))
+                }
+                Ok(Key::Backspace) => {
+                    if line.pop().is_some() {
+                        let _ = term.write_str("\x08 \x08");
+                    }
+                    continue;
+                }
+                Ok(Key::Char(c)) if !c.is_control() => {
+                    line.push(c);
+                    let _ = term.write_str(&c.to_string());
+                    continue;
+                }
+                _ => continue,
+            };
+            let exit = matches!(input, Input::Exit(_));
+            if tx.send(input).is_err() || exit {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn run_monitor(port: &mut dyn SerialPort, params: &MonitorParams) -> Result<MonitorExit, std::io::Error> {
+    port.set_timeout(Duration::from_millis(20))?;
+    port.clear(serialport::ClearBuffer::Input)?;
+
+    eprintln!(
+        "--- Monitor on {} at {} baud, Ctrl+] to quit{} ---",
+        port.name().unwrap_or_default(),
+        params.baud,
+        if params.reflash { ", Ctrl+R to reset and reflash" } else { "" }
+    );
+
+    let symbolizer = match &params.elf_path {
+        Some(elf_path) => Some(Symbolizer::new(elf_path)?),
+        None => None,
+    };
+
+    let rx = spawn_input_thread(params.reflash);
+    let start = Instant::now();
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0u8; 1024];
+    let mut line_start = true;
+    let mut line = Vec::new();
+    loop {
+        match port.read(&mut buffer) {
+            Ok(len) => {
+                for byte in buffer[..len].iter() {
+                    if line_start && params.timestamps {
+                        let elapsed = start.elapsed();
+                        write!(stdout, "[{:>5}.{:03}] ", elapsed.as_secs(), elapsed.subsec_millis())?;
+                    }
+                    line_start = false;
+                    match byte {
+                        // 终端可能处于 raw 模式，统一输出 \r\n
+                        b'\n' => {
+                            stdout.write_all(b"\r\n")?;
+                            line_start = true;
+                            if let Some(symbolizer) = &symbolizer {
+                                let text = String::from_utf8_lossy(&line);
+                                for (address, symbol) in symbolizer.symbolize_line(&text) {
+                                    write!(stdout, "    0x{:08x} => {}\r\n", address, symbol)?;
+                                }
+                            }
+                            line.clear();
+                        }
+                        b'\r' => {}
+                        _ => {
+                            stdout.write_all(&[*byte])?;
+                            line.push(*byte);
+                        }
+                    }
+                }
+                stdout.flush()?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+
+        loop {
+            match rx.try_recv() {
+                Ok(Input::Line(line)) => {
+                    port.write_all(line.as_bytes())?;
+                    port.write_all(b"\r\n")?;
+                    port.flush()?;
+                }
+                Ok(Input::Exit(exit)) => {
+                    eprintln!("\r\n--- Monitor exited ---");
+                    return Ok(exit);
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                // 标准输入已关闭，继续显示串口输出
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,600 @@
+use crate::read_flash::ReadFlashTrait;
+use crate::{SifliTool, WriteFlashParams};
+use std::fmt;
+use std::io::Write;
+
+// NVDS 区域以魔数开头，之后依次是按 `align` 对齐的记录：
+// tag (1) | status (1) | length (2, 小端) | data，tag 为 0xFF 表示后面是空闲空间。
+// 这个布局不是从 SiFli SDK 的 NVDS 实现中得来的，魔数和对齐可以通过 `NvdsLayout` 修改，
+// 用于真实的板子前需要对照目标固件确认
+const NVDS_MAGIC: u32 = 0x4E56_4453;
+const RECORD_HEADER_SIZE: usize = 4;
+const TAG_END: u8 = 0xFF;
+
+// status 中的标志位为 0 时有效，写入 flash 后只能把 1 改成 0
+const STATUS_NOT_VALID: u8 = 0x01;
+const STATUS_NOT_LOCKED: u8 = 0x02;
+const STATUS_NOT_ERASED: u8 = 0x04;
+// 新写入的记录：有效、未锁定、未删除
+const STATUS_NEW: u8 = 0xFF & !STATUS_NOT_VALID;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    /// 蓝牙地址，按小端存储，显示为 `AA:BB:CC:DD:EE:FF`
+    Address,
+    Text,
+    Bytes,
+}
+
+// 协议栈使用的 tag，校准和产品配置等其余 tag 按编号访问
+const KEYS: &[(&str, u8, ValueKind)] = &[
+    ("bd_addr", 0x01, ValueKind::Address),
+    ("device_name", 0x02, ValueKind::Text),
+];
+
+/// NVDS 中的一个 key：已知的名称或 tag 编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvdsKey {
+    pub tag: u8,
+}
+
+impl NvdsKey {
+    pub fn parse(s: &str) -> Result<Self, std::io::Error> {
+        let name = s.to_lowercase();
+        if let Some((_, tag, _)) = KEYS.iter().find(|(n, _, _)| *n == name) {
+            return Ok(Self { tag: *tag });
+        }
+        match crate::write_flash::str_to_u32(&name) {
+            Ok(tag) if tag < TAG_END as u32 => Ok(Self { tag: tag as u8 }),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid NVDS key '{}', expected a key name or a tag number below 0xFF",
+                    s
+                ),
+            )),
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        KEYS.iter()
+            .find(|(_, tag, _)| *tag == self.tag)
+            .map(|(name, _, _)| *name)
+    }
+
+    fn kind(&self) -> ValueKind {
+        KEYS.iter()
+            .find(|(_, tag, _)| *tag == self.tag)
+            .map(|(_, _, kind)| *kind)
+            .unwrap_or(ValueKind::Bytes)
+    }
+
+    /// 按 key 的类型解析命令行上的值：地址为 `AA:BB:CC:DD:EE:FF`，名称为字符串，其余为十六进制
+    pub fn parse_value(&self, s: &str) -> Result<Vec<u8>, std::io::Error> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid value '{}' for NVDS key {}", s, self),
+            )
+        };
+        match self.kind() {
+            ValueKind::Address => {
+                let mut bytes = s
+                    .split(':')
+                    .map(|b| u8::from_str_radix(b, 16).ok().filter(|_| b.len() == 2))
+                    .collect::<Option<Vec<u8>>>()
+                    .filter(|b| b.len() == 6)
+                    .ok_or_else(invalid)?;
+                bytes.reverse();
+                Ok(bytes)
+            }
+            ValueKind::Text => Ok(s.as_bytes().to_vec()),
+            ValueKind::Bytes => {
+                let hex: String = s
+                    .strip_prefix("0x")
+                    .unwrap_or(s)
+                    .chars()
+                    .filter(|c| !c.is_whitespace() && *c != ':')
+                    .collect();
+                if hex.is_empty() || hex.len() % 2 != 0 {
+                    return Err(invalid());
+                }
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(invalid)
+            }
+        }
+    }
+
+    pub fn format_value(&self, data: &[u8]) -> String {
+        match self.kind() {
+            ValueKind::Address if data.len() == 6 => data
+                .iter()
+                .rev()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(":"),
+            ValueKind::Text if std::str::from_utf8(data).is_ok() => {
+                format!("\"{}\"", String::from_utf8_lossy(data))
+            }
+            _ => data
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+impl fmt::Display for NvdsKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "0x{:02X}", self.tag),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NvdsRecord {
+    pub key: NvdsKey,
+    /// 记录头在区域内的偏移
+    pub offset: usize,
+    pub data: Vec<u8>,
+    /// 锁定的记录不能修改
+    pub locked: bool,
+}
+
+/// NVDS 区域的魔数和记录对齐方式，默认为小端的 0x4E564453 和 4 字节对齐
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvdsLayout {
+    pub magic: Vec<u8>,
+    pub align: usize,
+}
+
+impl Default for NvdsLayout {
+    fn default() -> Self {
+        Self {
+            magic: NVDS_MAGIC.to_le_bytes().to_vec(),
+            align: 4,
+        }
+    }
+}
+
+impl NvdsLayout {
+    // 第一条记录的偏移
+    fn header_size(&self) -> usize {
+        self.magic.len().next_multiple_of(self.align)
+    }
+
+    fn record_size(&self, len: usize) -> usize {
+        (RECORD_HEADER_SIZE + len).next_multiple_of(self.align)
+    }
+
+    fn check(&self) -> Result<(), std::io::Error> {
+        if self.magic.is_empty() || self.align == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The NVDS magic must not be empty and the alignment must not be 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Nvds {
+    pub address: u32,
+    pub size: u32,
+    /// 有效的记录，按在 flash 中的顺序排列
+    pub records: Vec<NvdsRecord>,
+    /// 已删除或未写完的记录数，整理后可以回收空间
+    pub stale: usize,
+    /// 第一个空闲字节的偏移
+    pub end: usize,
+    pub layout: NvdsLayout,
+}
+
+impl Nvds {
+    /// 解析 NVDS 区域，全部为 0xFF 的区域视为空
+    pub fn parse(address: u32, image: &[u8], layout: &NvdsLayout) -> Result<Self, std::io::Error> {
+        layout.check()?;
+        let mut nvds = Self {
+            address,
+            size: image.len() as u32,
+            records: Vec::new(),
+            stale: 0,
+            end: layout.header_size(),
+            layout: layout.clone(),
+        };
+        if image.iter().all(|b| *b == 0xFF) {
+            return Ok(nvds);
+        }
+        if !image.starts_with(&layout.magic) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("No NVDS found at 0x{:08X}", address),
+            ));
+        }
+
+        let mut offset = layout.header_size();
+        while offset + RECORD_HEADER_SIZE <= image.len() && image[offset] != TAG_END {
+            let status = image[offset + 1];
+            let len = u16::from_le_bytes([image[offset + 2], image[offset + 3]]) as usize;
+            let next = offset + layout.record_size(len);
+            if next > image.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "NVDS record at 0x{:08X} runs past the end of the region",
+                        address as usize + offset
+                    ),
+                ));
+            }
+            if status & STATUS_NOT_VALID != 0 || status & STATUS_NOT_ERASED == 0 {
+                nvds.stale += 1;
+            } else {
+                nvds.records.push(NvdsRecord {
+                    key: NvdsKey { tag: image[offset] },
+                    offset,
+                    data: image[offset + RECORD_HEADER_SIZE..offset + RECORD_HEADER_SIZE + len]
+                        .to_vec(),
+                    locked: status & STATUS_NOT_LOCKED == 0,
+                });
+            }
+            offset = next;
+        }
+        nvds.end = offset;
+        Ok(nvds)
+    }
+
+    /// 查找 key 的记录，同一个 tag 有多条有效记录时以最后写入的为准
+    pub fn get(&self, key: NvdsKey) -> Option<&NvdsRecord> {
+        self.records.iter().rev().find(|r| r.key == key)
+    }
+
+    fn used(&self) -> usize {
+        self.layout.header_size()
+            + self
+                .records
+                .iter()
+                .map(|r| self.layout.record_size(r.data.len()))
+                .sum::<usize>()
+    }
+
+    /// 在镜像中修改一个 key：长度不变时原地覆盖；否则把旧记录标记为删除并追加到末尾；
+    /// 空间不足时整理整个区域，丢弃已删除的记录
+    fn set(&self, image: &mut [u8], key: NvdsKey, value: &[u8]) -> Result<(), std::io::Error> {
+        if value.len() > u16::MAX as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "NVDS values are limited to 65535 bytes",
+            ));
+        }
+        let old = self.get(key);
+        if old.is_some_and(|r| r.locked) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("NVDS key {} is locked", key),
+            ));
+        }
+
+        let record = |tag: u8, data: &[u8]| -> Vec<u8> {
+            let mut bytes = vec![tag, STATUS_NEW];
+            bytes.extend((data.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(data);
+            bytes.resize(self.layout.record_size(data.len()), 0xFF);
+            bytes
+        };
+
+        if let Some(old) = old {
+            if old.data.len() == value.len() {
+                let start = old.offset + RECORD_HEADER_SIZE;
+                image[start..start + value.len()].copy_from_slice(value);
+                return Ok(());
+            }
+        }
+
+        let new = record(key.tag, value);
+        if self.end + new.len() <= image.len() {
+            image[..self.layout.magic.len()].copy_from_slice(&self.layout.magic);
+            if let Some(old) = old {
+                image[old.offset + 1] &= !STATUS_NOT_ERASED;
+            }
+            image[self.end..self.end + new.len()].copy_from_slice(&new);
+            return Ok(());
+        }
+
+        let mut compacted = self.layout.magic.clone();
+        compacted.resize(self.layout.header_size(), 0xFF);
+        for r in self.records.iter().filter(|r| r.key != key) {
+            let mut bytes = record(r.key.tag, &r.data);
+            if r.locked {
+                bytes[1] &= !STATUS_NOT_LOCKED;
+            }
+            compacted.extend(bytes);
+        }
+        compacted.extend(new);
+        if compacted.len() > image.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "NVDS region is full ({} of {} bytes used)",
+                    self.used(),
+                    self.size
+                ),
+            ));
+        }
+        image.fill(0xFF);
+        image[..compacted.len()].copy_from_slice(&compacted);
+        Ok(())
+    }
+}
+
+impl fmt::Display for Nvds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "NVDS 0x{:08X}: {} records, {} of {} bytes used",
+            self.address,
+            self.records.len(),
+            self.used(),
+            self.size
+        )?;
+        if self.stale > 0 {
+            write!(f, " ({} stale records)", self.stale)?;
+        }
+        for r in self.records.iter() {
+            write!(
+                f,
+                "\n  0x{:02X} {:<14} {:>4}  {}",
+                r.key.tag,
+                r.key.name().unwrap_or(""),
+                r.data.len(),
+                r.key.format_value(&r.data)
+            )?;
+            if r.locked {
+                write!(f, " [locked]")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NvdsParams {
+    /// NVDS 区域的起始地址和大小
+    pub address: u32,
+    pub size: u32,
+    pub layout: NvdsLayout,
+}
+
+pub trait NvdsTrait {
+    /// 读取并解析整个 NVDS 区域
+    fn config_dump(&mut self, params: &NvdsParams) -> Result<Nvds, std::io::Error>;
+    fn config_get(&mut self, params: &NvdsParams, key: NvdsKey) -> Result<Vec<u8>, std::io::Error>;
+    /// 修改一个 key，只写回内容发生变化的擦除块，返回写入的擦除块地址
+    fn config_set(
+        &mut self,
+        params: &NvdsParams,
+        key: NvdsKey,
+        value: &[u8],
+    ) -> Result<Vec<u32>, std::io::Error>;
+}
+
+impl NvdsTrait for SifliTool {
+    fn config_dump(&mut self, params: &NvdsParams) -> Result<Nvds, std::io::Error> {
+        let image = self.read_flash(params.address, params.size)?;
+        Nvds::parse(params.address, &image, &params.layout)
+    }
+
+    fn config_get(&mut self, params: &NvdsParams, key: NvdsKey) -> Result<Vec<u8>, std::io::Error> {
+        let nvds = self.config_dump(params)?;
+        nvds.get(key)
+            .map(|r| r.data.clone())
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("NVDS key {} not found", key),
+            ))
+    }
+
+    fn config_set(
+        &mut self,
+        params: &NvdsParams,
+        key: NvdsKey,
+        value: &[u8],
+    ) -> Result<Vec<u32>, std::io::Error> {
+        // stub 按擦除单位改写：NOR 和 NAND 使用 stub 识别到的扇区或块大小，SD 卡为 512 字节扇区
+        let block = self.erase_block_size(params.address)? as usize;
+        if params.address as usize % block != 0 || params.size as usize % block != 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "The NVDS region must be aligned to the {} byte erase block size",
+                    block
+                ),
+            ));
+        }
+
+        let image = self.read_flash(params.address, params.size)?;
+        let nvds = Nvds::parse(params.address, &image, &params.layout)?;
+        let mut new = image.clone();
+        nvds.set(&mut new, key, value)?;
+
+        // 只写回发生变化的擦除块，每个块单独作为一个文件交给 write_flash
+        let mut files = Vec::new();
+        let mut blocks = Vec::new();
+        for (i, (old, new)) in image.chunks(block).zip(new.chunks(block)).enumerate() {
+            if old == new {
+                continue;
+            }
+            let address = params.address + (i * block) as u32;
+            let mut file = tempfile::NamedTempFile::new()?;
+            file.write_all(new)?;
+            file.flush()?;
+            blocks.push(address);
+            files.push(file);
+        }
+        if files.is_empty() {
+            return Ok(blocks);
+        }
+
+        self.write_flash_with(&WriteFlashParams {
+            file_path: files
+                .iter()
+                .zip(blocks.iter())
+                .map(|(file, address)| format!("{}@0x{:08X}", file.path().display(), address))
+                .collect(),
+            verify: true,
+            no_compress: false,
+            erase_all: false,
+            public_key: None,
+            require_signed: false,
+            fw_verify_code: None,
+        })?;
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BD_ADDR: NvdsKey = NvdsKey { tag: 0x01 };
+    const DEVICE_NAME: NvdsKey = NvdsKey { tag: 0x02 };
+    const CALIBRATION: NvdsKey = NvdsKey { tag: 0x20 };
+
+    // 按 flash 中的格式拼出一个 `size` 字节的区域
+    fn region(size: usize, records: &[(u8, u8, &[u8])]) -> Vec<u8> {
+        let mut image = NVDS_MAGIC.to_le_bytes().to_vec();
+        for (tag, status, data) in records {
+            image.extend([*tag, *status]);
+            image.extend((data.len() as u16).to_le_bytes());
+            image.extend_from_slice(data);
+            image.resize(image.len().next_multiple_of(4), 0xFF);
+        }
+        image.resize(size, 0xFF);
+        image
+    }
+
+    #[test]
+    fn set_same_length_in_place() {
+        let image = region(
+            64,
+            &[
+                (0x01, STATUS_NEW, &[1, 2, 3, 4, 5, 6]),
+                (0x02, STATUS_NEW, b"sifli"),
+            ],
+        );
+        let nvds = Nvds::parse(0x1200_0000, &image, &NvdsLayout::default()).unwrap();
+        assert_eq!(nvds.records.len(), 2);
+        assert_eq!(nvds.end, 4 + 12 + 12);
+
+        let mut new = image.clone();
+        nvds.set(&mut new, BD_ADDR, &[6, 5, 4, 3, 2, 1]).unwrap();
+        // 只有数据本身发生变化
+        let changed: Vec<usize> = (0..image.len()).filter(|i| image[*i] != new[*i]).collect();
+        assert!(changed.iter().all(|i| (8..14).contains(i)));
+
+        let nvds = Nvds::parse(0x1200_0000, &new, &NvdsLayout::default()).unwrap();
+        assert_eq!(nvds.get(BD_ADDR).unwrap().data, [6, 5, 4, 3, 2, 1]);
+        assert_eq!(nvds.get(DEVICE_NAME).unwrap().data, b"sifli");
+        assert_eq!((nvds.stale, nvds.end), (0, 28));
+    }
+
+    #[test]
+    fn set_new_length_appends_and_marks_old_record_erased() {
+        let image = region(64, &[(0x02, STATUS_NEW, b"sifli")]);
+        let nvds = Nvds::parse(0x1200_0000, &image, &NvdsLayout::default()).unwrap();
+        let mut new = image.clone();
+        nvds.set(&mut new, DEVICE_NAME, b"sifli-watch").unwrap();
+
+        // 旧记录只清除了 STATUS_NOT_ERASED，数据保持原样
+        assert_eq!(new[5], STATUS_NEW & !STATUS_NOT_ERASED);
+        assert_eq!(&new[8..13], b"sifli");
+        let nvds = Nvds::parse(0x1200_0000, &new, &NvdsLayout::default()).unwrap();
+        assert_eq!(nvds.stale, 1);
+        assert_eq!(nvds.records.len(), 1);
+        assert_eq!(nvds.records[0].offset, 16);
+        assert_eq!(nvds.get(DEVICE_NAME).unwrap().data, b"sifli-watch");
+        assert_eq!(nvds.end, 16 + 4 + 12);
+
+        // 空白区域先写入 NVDS 头
+        let blank = vec![0xFFu8; 32];
+        let nvds = Nvds::parse(0x1200_0000, &blank, &NvdsLayout::default()).unwrap();
+        let mut new = blank.clone();
+        nvds.set(&mut new, BD_ADDR, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(new[..4], NVDS_MAGIC.to_le_bytes());
+        let nvds = Nvds::parse(0x1200_0000, &new, &NvdsLayout::default()).unwrap();
+        assert_eq!(nvds.get(BD_ADDR).unwrap().data, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn set_compacts_when_full() {
+        // 区域末尾只剩 4 字节，追加放不下，整理时丢弃已删除的记录并保留锁定标志
+        let image = region(
+            48,
+            &[
+                (0x02, STATUS_NEW & !STATUS_NOT_ERASED, b"old name"),
+                (0x20, STATUS_NEW & !STATUS_NOT_LOCKED, &[0xA5; 4]),
+                (0x02, STATUS_NEW, b"name"),
+                (0x01, STATUS_NEW, &[1, 2, 3, 4, 5, 6]),
+            ],
+        );
+        let old = Nvds::parse(0x1200_0000, &image, &NvdsLayout::default()).unwrap();
+        assert_eq!((old.stale, old.end), (1, 44));
+
+        let mut new = image.clone();
+        old.set(&mut new, DEVICE_NAME, b"new name").unwrap();
+        let nvds = Nvds::parse(0x1200_0000, &new, &NvdsLayout::default()).unwrap();
+        assert_eq!(nvds.stale, 0);
+        let keys: Vec<u8> = nvds.records.iter().map(|r| r.key.tag).collect();
+        assert_eq!(keys, [0x20, 0x01, 0x02]);
+        assert!(nvds.get(CALIBRATION).unwrap().locked);
+        assert_eq!(nvds.get(CALIBRATION).unwrap().data, [0xA5; 4]);
+        assert_eq!(nvds.get(BD_ADDR).unwrap().data, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(nvds.get(DEVICE_NAME).unwrap().data, b"new name");
+        assert!(new[nvds.end..].iter().all(|b| *b == 0xFF));
+
+        // 整理后仍然放不下时报错
+        let mut full = image.clone();
+        let err = old.set(&mut full, DEVICE_NAME, &[0; 32]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn custom_layout() {
+        let layout = NvdsLayout {
+            magic: b"CFG".to_vec(),
+            align: 8,
+        };
+        let blank = vec![0xFFu8; 64];
+        let nvds = Nvds::parse(0x1200_0000, &blank, &layout).unwrap();
+        let mut new = blank.clone();
+        nvds.set(&mut new, DEVICE_NAME, b"sifli").unwrap();
+        // 魔数补齐到 8 字节，记录头和数据一起按 8 字节对齐
+        assert_eq!(&new[..3], b"CFG");
+        assert_eq!(new[8..12], [0x02, STATUS_NEW, 5, 0]);
+        assert_eq!(&new[12..17], b"sifli");
+
+        let nvds = Nvds::parse(0x1200_0000, &new, &layout).unwrap();
+        assert_eq!(nvds.get(DEVICE_NAME).unwrap().data, b"sifli");
+        assert_eq!(nvds.end, 8 + 16);
+        // 魔数不一致时不会当作 NVDS 区域修改
+        assert!(Nvds::parse(0x1200_0000, &new, &NvdsLayout::default()).is_err());
+    }
+
+    #[test]
+    fn set_locked_key_fails() {
+        let image = region(64, &[(0x20, STATUS_NEW & !STATUS_NOT_LOCKED, &[0xA5; 4])]);
+        let nvds = Nvds::parse(0x1200_0000, &image, &NvdsLayout::default()).unwrap();
+        let mut new = image.clone();
+        for value in [&[0x5A; 4][..], &[0x5A; 8][..]] {
+            let err = nvds.set(&mut new, CALIBRATION, value).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+            assert_eq!(new, image);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use probe_rs::probe::Probe;
+use probe_rs::probe::list::{DebugProbeInfo, Lister};
+use serialport::SerialPortType;
+
+// 常见的 USB 转串口芯片，用于 `list_ports --known` 过滤
+const KNOWN_ADAPTERS: &[(u16, u16, &str)] = &[
+    (0x1A86, 0x7523, "CH340"),
+    (0x1A86, 0x5523, "CH341"),
+    (0x1A86, 0x55D3, "CH343"),
+    (0x1A86, 0x55D4, "CH9102"),
+    (0x10C4, 0xEA60, "CP210x"),
+    (0x10C4, 0xEA70, "CP2105"),
+    (0x10C4, 0xEA71, "CP2108"),
+];
+
+#[derive(Debug, Clone)]
+pub struct PortInfo {
+    pub port_name: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub description: Option<String>,
+    /// 已知的 USB 转串口芯片名称
+    pub adapter: Option<&'static str>,
+    /// 对应的 probe-rs SiFli UART 探针标识，`None` 表示 probe-rs 未枚举到该端口
+    pub probe: Option<String>,
+}
+
+impl PortInfo {
+    /// 可直接传给 `--port` 的值，只有能被 probe-rs 打开的端口才可用于下载
+    pub fn port_arg(&self) -> Option<&str> {
+        self.probe.as_ref().map(|_| self.port_name.as_str())
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.adapter.is_some() || self.probe.is_some()
+    }
+}
+
+// SiFli UART 探针的序列号就是端口名。先找完全相同的，找不到时再按包含关系匹配，
+// 避免 `/dev/ttyUSB1` 误匹配 `/dev/ttyUSB10` 的探针
+fn find_probe(probes: &[DebugProbeInfo], port_name: &str) -> Option<usize> {
+    let serials: Vec<Option<&str>> = probes
+        .iter()
+        .map(|probe| probe.serial_number.as_deref())
+        .collect();
+    find_serial(&serials, port_name)
+}
+
+fn find_serial(serials: &[Option<&str>], port_name: &str) -> Option<usize> {
+    serials
+        .iter()
+        .position(|s| *s == Some(port_name))
+        .or_else(|| {
+            serials
+                .iter()
+                .position(|s| s.is_some_and(|s| s.contains(port_name)))
+        })
+}
+
+/// 枚举系统中的串口以及 probe-rs 能识别的 SiFli UART 探针。
+///
+/// `known_only` 为 true 时只返回已知的 USB 转串口芯片或 probe-rs 能打开的端口。
+pub fn list_ports(known_only: bool) -> Result<(Vec<PortInfo>, Vec<DebugProbeInfo>), std::io::Error> {
+    let mut probes = Lister::new().list_all();
+
+    let mut ports = Vec::new();
+    for port in serialport::available_ports()? {
+        let (vid, pid, serial_number, description) = match port.port_type {
+            SerialPortType::UsbPort(info) => (
+                Some(info.vid),
+                Some(info.pid),
+                info.serial_number,
+                match (info.manufacturer, info.product) {
+                    (Some(m), Some(p)) => Some(format!("{} {}", m, p)),
+                    (m, p) => m.or(p),
+                },
+            ),
+            SerialPortType::PciPort => (None, None, None, Some("PCI".to_string())),
+            SerialPortType::BluetoothPort => (None, None, None, Some("Bluetooth".to_string())),
+            SerialPortType::Unknown => (None, None, None, None),
+        };
+        let adapter = KNOWN_ADAPTERS
+            .iter()
+            .find(|(v, p, _)| Some(*v) == vid && Some(*p) == pid)
+            .map(|(_, _, name)| *name);
+        let probe =
+            find_probe(&probes, &port.port_name).map(|index| probes.remove(index).identifier);
+
+        let info = PortInfo {
+            port_name: port.port_name,
+            vid,
+            pid,
+            serial_number,
+            description,
+            adapter,
+            probe,
+        };
+        if known_only && !info.is_known() {
+            continue;
+        }
+        ports.push(info);
+    }
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+
+    // 剩下的是没有对应串口的探针（例如 J-Link）
+    Ok((ports, probes))
+}
+
+/// 打开 `port_name` 对应的 probe-rs 探针。
+///
+/// 优先选择序列号与端口名完全相同的探针，避免 `/dev/ttyUSB1` 误匹配 `/dev/ttyUSB10`。
+pub(crate) fn open_probe(port_name: &str) -> Result<Probe, std::io::Error> {
+    let probes = Lister::new().list_all();
+
+    let Some(probe) = find_probe(&probes, port_name).map(|index| &probes[index]) else {
+        let available: Vec<String> = probes
+            .iter()
+            .filter_map(|probe| probe.serial_number.clone())
+            .collect();
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "No probe found for port '{}' (available: [{}]), run `sftool list_ports` to see usable ports",
+                port_name,
+                available.join(", ")
+            ),
+        ));
+    };
+    probe
+        .open()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+}
+
+enum PortSelector<'a> {
+    Path(&'a str),
+    Glob(&'a str),
+    Usb {
+        vid: u16,
+        pid: u16,
+        serial: Option<&'a str>,
+    },
+    Serial(&'a str),
+}
+
+fn parse_hex_u16(s: &str) -> Option<u16> {
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+impl<'a> PortSelector<'a> {
+    fn parse(spec: &'a str) -> Result<Self, std::io::Error> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid port selector '{}', expected usb:VID:PID[:SERIAL] or serial:SERIAL",
+                    spec
+                ),
+            )
+        };
+
+        if let Some(rest) = spec.strip_prefix("usb:") {
+            let mut parts = rest.splitn(3, ':');
+            let vid = parts.next().and_then(parse_hex_u16).ok_or_else(invalid)?;
+            let pid = parts.next().and_then(parse_hex_u16).ok_or_else(invalid)?;
+            let serial = parts.next().filter(|s| !s.is_empty());
+            Ok(PortSelector::Usb { vid, pid, serial })
+        } else if let Some(serial) = spec.strip_prefix("serial:") {
+            if serial.is_empty() {
+                return Err(invalid());
+            }
+            Ok(PortSelector::Serial(serial))
+        } else if spec.contains(['*', '?']) {
+            Ok(PortSelector::Glob(spec))
+        } else {
+            Ok(PortSelector::Path(spec))
+        }
+    }
+
+    fn matches_usb(&self, vid: u16, pid: u16, serial_number: Option<&str>) -> bool {
+        match self {
+            PortSelector::Usb {
+                vid: v,
+                pid: p,
+                serial,
+            } => *v == vid && *p == pid && serial.is_none_or(|s| Some(s) == serial_number),
+            PortSelector::Serial(serial) => Some(*serial) == serial_number,
+            _ => false,
+        }
+    }
+
+    // probe-rs 的 SiFli UART 探针把端口名当作序列号，拿不到 USB 序列号，
+    // 所以只能按 VID/PID 匹配，指定了序列号的选择器不使用探针补充
+    fn matches_probe(&self, vid: u16, pid: u16) -> bool {
+        matches!(
+            self,
+            PortSelector::Usb { vid: v, pid: p, serial: None } if *v == vid && *p == pid
+        )
+    }
+}
+
+/// 将 `--port` 参数展开为实际的串口列表。
+///
+/// 支持以下几种写法：
+/// - 设备路径，例如 `/dev/ttyUSB0`、`COM3`，原样返回
+/// - 通配符，包含 `*` 或 `?`，例如 `/dev/ttyUSB*`
+/// - `usb:VID:PID[:SERIAL]`，按 USB VID/PID（十六进制）及可选的序列号匹配
+/// - `serial:SERIAL`，按 USB 序列号匹配
+///
+/// 后三种写法会与当前系统中存在的串口逐一匹配，设备路径在重新插拔后发生变化时仍能选中同一块板子。
+/// 不带序列号的 `usb:VID:PID` 还会匹配 probe-rs 枚举到的 SiFli UART 探针。
+pub fn expand_port_pattern(pattern: &str) -> Result<Vec<String>, std::io::Error> {
+    let selector = PortSelector::parse(pattern)?;
+    if let PortSelector::Path(path) = selector {
+        return Ok(vec![path.to_string()]);
+    }
+
+    let available = serialport::available_ports()?;
+    let mut ports: Vec<String> = available
+        .iter()
+        .filter(|p| match (&selector, &p.port_type) {
+            (PortSelector::Glob(glob), _) => wildcard_match(glob, &p.port_name),
+            (_, SerialPortType::UsbPort(info)) => {
+                selector.matches_usb(info.vid, info.pid, info.serial_number.as_deref())
+            }
+            _ => false,
+        })
+        .map(|p| p.port_name.clone())
+        .collect();
+
+    // 部分平台上 serialport 拿不到 USB 信息，再用 probe-rs 枚举到的探针补充
+    if let PortSelector::Usb { serial: None, .. } = selector {
+        for probe in Lister::new().list_all() {
+            let Some(port_name) = probe.serial_number.as_deref() else {
+                continue;
+            };
+            if selector.matches_probe(probe.vendor_id, probe.product_id)
+                && available.iter().any(|p| p.port_name == port_name)
+            {
+                ports.push(port_name.to_string());
+            }
+        }
+    }
+    ports.sort();
+    ports.dedup();
+
+    if ports.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No serial port matches '{}'", pattern),
+        ));
+    }
+    Ok(ports)
+}
+
+/// 将 `--port` 参数解析为唯一的串口设备路径，匹配到多个端口时返回错误。
+pub fn resolve_port(spec: &str) -> Result<String, std::io::Error> {
+    let mut ports = expand_port_pattern(spec)?;
+    if ports.len() > 1 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' matches multiple ports ({}), please be more specific",
+                spec,
+                ports.join(", ")
+            ),
+        ));
+    }
+    Ok(ports.remove(0))
+}
+
+// 简单的通配符匹配，`*` 匹配任意长度字符，`?` 匹配单个字符
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut star_t = 0;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some(p);
+            star_t = t;
+            p += 1;
+        } else if let Some(s) = star {
+            p = s + 1;
+            star_t += 1;
+            t = star_t;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_match_star_and_question_mark() {
+        assert!(wildcard_match("/dev/ttyUSB*", "/dev/ttyUSB0"));
+        assert!(wildcard_match("/dev/ttyUSB*", "/dev/ttyUSB"));
+        assert!(wildcard_match("/dev/ttyUSB?", "/dev/ttyUSB1"));
+        assert!(!wildcard_match("/dev/ttyUSB?", "/dev/ttyUSB10"));
+        assert!(wildcard_match("COM*", "COM12"));
+        assert!(wildcard_match("*ACM*", "/dev/ttyACM3"));
+        assert!(wildcard_match("/dev/tty*0", "/dev/ttyUSB10"));
+        assert!(!wildcard_match("/dev/ttyUSB*", "/dev/ttyACM0"));
+        assert!(!wildcard_match("COM?", "COM"));
+    }
+
+    #[test]
+    fn find_serial_prefers_exact_match() {
+        let serials = [Some("/dev/ttyUSB10"), None, Some("/dev/ttyUSB1")];
+        assert_eq!(find_serial(&serials, "/dev/ttyUSB1"), Some(2));
+        assert_eq!(find_serial(&serials, "/dev/ttyUSB10"), Some(0));
+        // 没有完全相同的序列号时按包含关系匹配
+        assert_eq!(find_serial(&[None, Some("COM9 (SiFli)")], "COM9"), Some(1));
+        assert_eq!(find_serial(&serials, "/dev/ttyACM0"), None);
+    }
+
+    #[test]
+    fn parse_usb_selector() {
+        let selector = PortSelector::parse("usb:1a86:0x55D3").unwrap();
+        assert!(matches!(
+            selector,
+            PortSelector::Usb {
+                vid: 0x1A86,
+                pid: 0x55D3,
+                serial: None
+            }
+        ));
+        assert!(selector.matches_usb(0x1A86, 0x55D3, None));
+        assert!(selector.matches_usb(0x1A86, 0x55D3, Some("5434012345")));
+        assert!(!selector.matches_usb(0x1A86, 0x7523, None));
+
+        let selector = PortSelector::parse("usb:1A86:55D3:5434012345").unwrap();
+        assert!(matches!(
+            selector,
+            PortSelector::Usb {
+                serial: Some("5434012345"),
+                ..
+            }
+        ));
+        assert!(selector.matches_usb(0x1A86, 0x55D3, Some("5434012345")));
+        assert!(!selector.matches_usb(0x1A86, 0x55D3, Some("5434099999")));
+        assert!(!selector.matches_usb(0x1A86, 0x55D3, None));
+
+        // 序列号为空时等同于不指定
+        let selector = PortSelector::parse("usb:1A86:55D3:").unwrap();
+        assert!(matches!(selector, PortSelector::Usb { serial: None, .. }));
+    }
+
+    #[test]
+    fn parse_serial_and_path_selectors() {
+        let selector = PortSelector::parse("serial:5434012345").unwrap();
+        assert!(matches!(selector, PortSelector::Serial("5434012345")));
+        assert!(selector.matches_usb(0x10C4, 0xEA60, Some("5434012345")));
+        assert!(!selector.matches_usb(0x10C4, 0xEA60, None));
+
+        assert!(matches!(
+            PortSelector::parse("/dev/ttyUSB0").unwrap(),
+            PortSelector::Path("/dev/ttyUSB0")
+        ));
+        assert!(matches!(
+            PortSelector::parse("/dev/ttyUSB*").unwrap(),
+            PortSelector::Glob("/dev/ttyUSB*")
+        ));
+        assert!(!PortSelector::parse("COM3").unwrap().matches_usb(0x1A86, 0x7523, None));
+    }
+
+    #[test]
+    fn probe_fallback_matches_vid_pid_only() {
+        let selector = PortSelector::parse("usb:1A86:55D3").unwrap();
+        assert!(selector.matches_probe(0x1A86, 0x55D3));
+        assert!(!selector.matches_probe(0x1A86, 0x7523));
+
+        // 探针的序列号是端口名而不是 USB 序列号，带序列号的选择器不能用探针匹配
+        let selector = PortSelector::parse("usb:1A86:55D3:5434012345").unwrap();
+        assert!(!selector.matches_probe(0x1A86, 0x55D3));
+        assert!(!PortSelector::parse("serial:5434012345").unwrap().matches_probe(0x1A86, 0x55D3));
+        assert!(!PortSelector::parse("/dev/ttyUSB*").unwrap().matches_probe(0x1A86, 0x55D3));
+    }
+
+    #[test]
+    fn parse_invalid_selectors() {
+        for spec in ["usb:", "usb:1A86", "usb:xyz:55D3", "usb:1A86:123456", "serial:"] {
+            assert!(PortSelector::parse(spec).is_err(), "{}", spec);
+        }
+    }
+}
@@ -18,11 +18,59 @@ pub enum Command {
     #[strum(to_string = "burn_write 0x{address:08x} 0x{len:08x}\r")]
     Write { address: u32, len: u32 },
 
+    /// 先输出 flash 信息和 `start_trans`，再输出原始数据，最后输出 `CRC:0x%08x`
+    #[strum(to_string = "burn_read 0x{address:08x} 0x{len:08x}\r")]
+    Read { address: u32, len: u32 },
+
     #[strum(to_string = "burn_reset\r")]
     SoftReset,
 
     #[strum(to_string = "burn_speed {baud} {delay}\r")]
     SetBaud { baud: u32, delay: u32 },
+
+    #[strum(to_string = "efuse_uid_read\r")]
+    EfuseUidRead,
+
+    #[strum(to_string = "efuse_read {bit_offset} {bytes}\r")]
+    EfuseRead { bit_offset: u32, bytes: u32 },
+
+    #[strum(to_string = "efuse_write {bit_offset} {bytes} {data}\r")]
+    EfuseWrite {
+        bit_offset: u32,
+        bytes: u32,
+        /// 十六进制字符串
+        data: String,
+    },
+
+    /// 把固件校验码写入 OTP 出厂配置（FACTORY_CFG_ID_FWVERIFY）并回读比较，长度为十进制
+    #[strum(to_string = "fw_enc_wr {len} {code}\r")]
+    FwEncWrite {
+        len: u32,
+        /// 十六进制字符串
+        code: String,
+    },
+
+    #[strum(to_string = "otp_fwenc_read\r")]
+    OtpFwencRead,
+
+    // 以下命令只有工厂校准 stub 支持
+    #[strum(to_string = "crystal_cali {pin} {ppm} {timeout}\r")]
+    CrystalCali { pin: u32, ppm: u32, timeout: u32 },
+
+    #[strum(to_string = "battery_cali {voltage} {range} {tolerance} {r1} {r2}\r")]
+    BatteryCali {
+        voltage: u32,
+        range: u32,
+        tolerance: u32,
+        r1: u32,
+        r2: u32,
+    },
+
+    #[strum(to_string = "otp_factory_read\r")]
+    OtpFactoryRead,
+
+    #[strum(to_string = "reboot\r")]
+    Reboot,
 }
 
 #[derive(EnumString, Display, Debug, Clone, PartialEq, Eq)]
@@ -40,10 +88,33 @@ const RESPONSE_STR_TABLE: [&str; 3] = ["OK", "Fail", "RX_WAIT"];
 pub trait RamCommand {
     fn command(&mut self, cmd: Command) -> Result<Response, std::io::Error>;
     fn send_data(&mut self, data: &[u8]) -> Result<Response, std::io::Error>;
+    /// 发送命令并收集 stub 的文本输出，直到出现 `end_markers` 中任意一个标记所在的整行为止。
+    /// 返回收到的全部文本（包含结束标记），由调用方解析。
+    fn command_output(&mut self, cmd: Command, end_markers: &[&str]) -> Result<String, std::io::Error>;
 }
 
 const TIMEOUT: u128 = 4000; //ms
 
+/// 解析 stub 在 `start` 和 `end` 两个标记之间输出的十六进制字节，忽略其中的空白和分隔符
+pub(crate) fn parse_hex_output(output: &str, start: &str, end: &str) -> Option<Vec<u8>> {
+    let from = output.find(start)? + start.len();
+    let to = from + output.get(from..)?.find(end)?;
+    let hex: Vec<u8> = output
+        .get(from..to)?
+        .bytes()
+        .filter(|b| b.is_ascii_hexdigit())
+        .collect();
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+// RT-Thread msh 在命令不存在时输出的提示，说明当前 stub 版本不支持该命令
+const COMMAND_NOT_FOUND: &str = "command not found";
+
 impl RamCommand for SifliTool {
     fn command(&mut self, cmd: Command) -> Result<Response, std::io::Error> {
         self.port.write_all(cmd.to_string().as_bytes())?;
@@ -135,4 +206,61 @@ impl RamCommand for SifliTool {
             }
         }
     }
+
+    fn command_output(&mut self, cmd: Command, end_markers: &[&str]) -> Result<String, Error> {
+        let cmd_str = cmd.to_string();
+        self.port.write_all(cmd_str.as_bytes())?;
+        self.port.flush()?;
+        self.port.clear(serialport::ClearBuffer::All)?;
+
+        let timeout = match cmd {
+            // 校准命令自身带有超时时间（秒），额外留出 5 秒
+            Command::CrystalCali { timeout, .. } => (timeout as u128 + 5) * 1000,
+            Command::BatteryCali { .. } => 10 * 1000,
+            _ => TIMEOUT,
+        };
+
+        let mut buffer = Vec::new();
+        let mut marker_end: Option<usize> = None;
+        let now = std::time::SystemTime::now();
+        loop {
+            let elapsed = now.elapsed().unwrap().as_millis();
+            if elapsed > timeout {
+                // 已经收到结束标记，只是没有等到换行，直接返回
+                if marker_end.is_some() {
+                    break;
+                }
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timeout"));
+            }
+
+            let mut byte = [0];
+            let ret = self.port.read_exact(&mut byte);
+            if ret.is_err() {
+                continue;
+            }
+            buffer.push(byte[0]);
+
+            if let Some(end) = marker_end {
+                if buffer[end..].contains(&b'\n') {
+                    break;
+                }
+                continue;
+            }
+
+            if buffer.ends_with(COMMAND_NOT_FOUND.as_bytes()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("The stub does not support `{}`", cmd_str.trim_end()),
+                ));
+            }
+            if end_markers
+                .iter()
+                .any(|marker| buffer.ends_with(marker.as_bytes()))
+            {
+                marker_end = Some(buffer.len());
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
 }
@@ -9,4 +9,30 @@ pub static CHIP_FILE_NAME: phf::Map<&'static str, &'static str> = phf_map! {
     "sf32lb52_nor" => "ram_patch_52X.bin",
     "sf32lb52_nand" => "ram_patch_52X_NAND.bin",
     "sf32lb52_sd" => "ram_patch_52X_SD.bin",
-};
\ No newline at end of file
+};
+
+// 工厂校准 stub，与存储器类型无关
+pub static CALI_FILE_NAME: phf::Map<&'static str, &'static str> = phf_map! {
+    "sf32lb52" => "factory_cali.bin",
+};
+
+/// 下载到 RAM 中运行的 stub
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StubKind {
+    /// 烧录 stub，根据芯片和存储器类型选择
+    Flash,
+    /// 工厂校准 stub
+    FactoryCali,
+}
+
+impl StubKind {
+    /// 返回 stub 的文件名和加载地址
+    pub(crate) fn image(&self, chip: &str, memory_type: &str) -> Option<(&'static str, u64)> {
+        match self {
+            StubKind::Flash => CHIP_FILE_NAME
+                .get(format!("{}_{}", chip, memory_type).as_str())
+                .map(|file| (*file, 0x2005_A000)),
+            StubKind::FactoryCali => CALI_FILE_NAME.get(chip).map(|file| (*file, 0x2005_0000)),
+        }
+    }
+}
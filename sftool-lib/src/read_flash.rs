@@ -0,0 +1,231 @@
+use crate::SifliTool;
+use crate::ram_command::Command;
+use crate::sd_card::{SECTOR_SIZE, sd_address};
+use crate::write_flash::CRC;
+use indicatif::ProgressStyle;
+use std::io::Read;
+
+// 每条 burn_read 读取的长度。stub 在 start_trans 之后会先等待约 1 秒再发送数据，
+// 分块过小会明显拖慢读取速度
+const READ_CHUNK: u32 = 256 * 1024;
+
+// 在这段时间内没有收到任何数据时认为 stub 已经停止响应
+const IDLE_TIMEOUT: u128 = 4000; //ms
+
+// burn_read 的输出依次为：flash 信息行、`start_trans`、原始数据、`CRC:0x%08x`、`OK`。
+// 控制台开启了 RT_DEVICE_FLAG_STREAM 时换行会被转换为 `\r\n`，stub 只在发送数据期间关闭该标志
+const START_TRANS: &[u8] = b"start_trans";
+
+fn invalid(address: u32, reason: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Failed to read flash at 0x{:08X}: {}", address, reason),
+    )
+}
+
+// 返回原始数据在输出中的起始位置，输出还不完整时返回 `None`
+fn data_start(output: &[u8]) -> Option<usize> {
+    let marker = output
+        .windows(START_TRANS.len())
+        .position(|w| w == START_TRANS)?;
+    let newline = output[marker..].iter().position(|b| *b == b'\n')?;
+    Some(marker + newline + 1)
+}
+
+// stub 在参数错误或没有接 flash 时输出 `Fail`
+fn is_fail(text: &[u8]) -> bool {
+    text.windows(4).any(|w| w == b"Fail")
+}
+
+/// 解析 `burn_read` 的完整输出，取出 `len` 字节数据并用 stub 输出的 CRC 校验
+pub(crate) fn parse_read_output(
+    output: &[u8],
+    address: u32,
+    len: u32,
+) -> Result<Vec<u8>, std::io::Error> {
+    let Some(start) = data_start(output) else {
+        let reason = if is_fail(output) {
+            "the stub reported Fail"
+        } else {
+            "missing start_trans"
+        };
+        return Err(invalid(address, reason));
+    };
+    let end = start + len as usize;
+    if output.len() < end {
+        return Err(invalid(address, "incomplete data"));
+    }
+    let data = &output[start..end];
+    let trailer = String::from_utf8_lossy(&output[end..]);
+    let Some(crc) = trailer
+        .split_once("CRC:0x")
+        .and_then(|(_, rest)| rest.get(..8))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+    else {
+        return Err(invalid(address, "missing CRC"));
+    };
+    let actual = CRC.checksum(data);
+    if crc != actual {
+        return Err(invalid(
+            address,
+            &format!("CRC mismatch (stub 0x{:08X}, data 0x{:08X})", crc, actual),
+        ));
+    }
+    if !trailer.contains("OK") {
+        return Err(invalid(address, "missing OK"));
+    }
+    Ok(data.to_vec())
+}
+
+pub trait ReadFlashTrait {
+    /// 通过 stub 读取 `[address, address + len)`，NAND 没有映射到地址空间，也可以读取。
+    /// SD 卡的地址是卡内的字节偏移
+    fn read_flash(&mut self, address: u32, len: u32) -> Result<Vec<u8>, std::io::Error>;
+}
+
+impl SifliTool {
+    // 发送一条 burn_read 并收集输出，直到数据之后出现 `OK` 或 `Fail` 所在的整行
+    pub(crate) fn read_chunk(
+        &mut self,
+        address: u32,
+        len: u32,
+        progress: &dyn Fn(u64),
+    ) -> Result<Vec<u8>, std::io::Error> {
+        self.port.clear(serialport::ClearBuffer::Input)?;
+        self.port
+            .write_all(Command::Read { address, len }.to_string().as_bytes())?;
+        self.port.flush()?;
+
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 4096];
+        let mut received = 0u64;
+        let mut last = std::time::SystemTime::now();
+        loop {
+            if last.elapsed().unwrap().as_millis() > IDLE_TIMEOUT {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("Timeout reading flash at 0x{:08X}", address),
+                ));
+            }
+            let n = match self.port.read(&mut buffer) {
+                Ok(0) => continue,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            output.extend_from_slice(&buffer[..n]);
+            last = std::time::SystemTime::now();
+
+            let Some(start) = data_start(&output) else {
+                if is_fail(&output) && output.ends_with(b"\n") {
+                    break;
+                }
+                continue;
+            };
+            let data_len = std::cmp::min(output.len() - start, len as usize) as u64;
+            progress(data_len - received);
+            received = data_len;
+
+            let trailer = &output[std::cmp::min(start + len as usize, output.len())..];
+            if received == len as u64
+                && (trailer.windows(2).any(|w| w == b"OK") || is_fail(trailer))
+                && trailer.ends_with(b"\n")
+            {
+                break;
+            }
+        }
+        parse_read_output(&output, address, len)
+    }
+}
+
+impl ReadFlashTrait for SifliTool {
+    fn read_flash(&mut self, address: u32, len: u32) -> Result<Vec<u8>, std::io::Error> {
+        // SD 卡的地址是卡内的字节偏移，stub 按 512 字节的扇区读取，需要扩展到扇区边界
+        let (read_address, skip, read_len) = if self.base.memory_type == "sd" {
+            let start = address - address % SECTOR_SIZE;
+            let end = (address as u64 + len as u64).next_multiple_of(SECTOR_SIZE as u64);
+            let read_len = (end - start as u64) as u32;
+            (
+                sd_address(start as u64, read_len as u64)?,
+                (address - start) as usize,
+                read_len,
+            )
+        } else {
+            (address, 0, len)
+        };
+
+        let progress_bar = self.base.new_progress_bar(read_len as u64);
+        if !self.base.quiet {
+            progress_bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "[{prefix}] Read at {msg}... {wide_bar} {bytes_per_sec} {percent_precise}%",
+                    )
+                    .unwrap()
+                    .progress_chars("=>-"),
+            );
+            progress_bar.set_message(format!("0x{:08X}", address));
+            progress_bar.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+        }
+
+        let mut data = Vec::with_capacity(read_len as usize);
+        while (data.len() as u32) < read_len {
+            let chunk_address = read_address + data.len() as u32;
+            let chunk_len = std::cmp::min(read_len - data.len() as u32, READ_CHUNK);
+            let chunk = self.read_chunk(chunk_address, chunk_len, &|n| progress_bar.inc(n))?;
+            data.extend(chunk);
+        }
+
+        if !self.base.quiet {
+            progress_bar.finish_with_message(format!("0x{:08X}", address));
+        }
+        Ok(data[skip..skip + len as usize].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按 stub 中 burn_read 的格式串拼出的输出，控制台开启了 RT_DEVICE_FLAG_STREAM
+    fn stub_output(data: &[u8], crc: u32) -> Vec<u8> {
+        let mut output = b"burn_read 0x12000000 0x00000013\r\n".to_vec();
+        output.extend_from_slice(b"RT_DEVICE_FLAG_STREAM\r\n");
+        output.extend_from_slice(
+            b"addr:0x12000000, size:0x1000000 sector:0x1000 page:0x100 id:0x1640c8\r\n",
+        );
+        output.extend_from_slice(b"start_trans\r\n");
+        output.extend_from_slice(data);
+        output.extend_from_slice(format!("CRC:0x{:08x}\r\nOK\r\n", crc).as_bytes());
+        output
+    }
+
+    #[test]
+    fn parse_burn_read_output() {
+        // 数据中包含换行、`OK` 和 `start_trans`，不能影响解析
+        let data = b"\nOK\r\nstart_trans\n\xFF\x00".to_vec();
+        let crc = CRC.checksum(&data);
+        let output = stub_output(&data, crc);
+        assert_eq!(
+            parse_read_output(&output, 0x1200_0000, data.len() as u32).unwrap(),
+            data
+        );
+        assert!(parse_read_output(&output, 0x1200_0000, data.len() as u32 + 1).is_err());
+        let corrupted = stub_output(&data, crc ^ 1);
+        assert!(parse_read_output(&corrupted, 0x1200_0000, data.len() as u32).is_err());
+    }
+
+    #[test]
+    fn parse_burn_read_without_stream_flag_and_fail() {
+        let data = [0x5Au8; 32];
+        let mut output =
+            b"addr:0x62000000, size:0x8000000 sector:0x20000 page:0x800 id:0x21c8\nstart_trans\n"
+                .to_vec();
+        output.extend_from_slice(&data);
+        output.extend_from_slice(format!("CRC:0x{:08x}\nOK\n", CRC.checksum(&data)).as_bytes());
+        assert_eq!(parse_read_output(&output, 0x6200_0000, 32).unwrap(), data);
+
+        assert!(parse_read_output(b"burn_read 0x62000000\r\nFail\r\n", 0x6200_0000, 32).is_err());
+    }
+}
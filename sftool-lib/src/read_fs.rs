@@ -0,0 +1,129 @@
+use crate::SifliTool;
+use crate::littlefs::LittleFs;
+use crate::read_flash::ReadFlashTrait;
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsType {
+    Fat,
+    LittleFs,
+}
+
+impl fmt::Display for FsType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsType::Fat => write!(f, "FAT"),
+            FsType::LittleFs => write!(f, "littlefs"),
+        }
+    }
+}
+
+/// 统计提取出的文件
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtractStats {
+    pub files: usize,
+    pub dirs: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReadFsInfo {
+    pub fs_type: FsType,
+    pub stats: ExtractStats,
+}
+
+impl fmt::Display for ReadFsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: extracted {} files in {} directories ({} bytes)",
+            self.fs_type, self.stats.files, self.stats.dirs, self.stats.bytes
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadFsParams {
+    /// 分区起始地址和大小
+    pub address: u32,
+    pub size: u32,
+    /// 提取到的主机目录
+    pub output: String,
+}
+
+/// 根据引导扇区或超级块判断镜像中的文件系统类型
+pub fn detect_fs(image: &[u8]) -> Option<FsType> {
+    let boot_signature = image.get(510..512) == Some(&[0x55, 0xAA][..]);
+    let fat_name = |offset: usize| image.get(offset..offset + 3) == Some(&b"FAT"[..]);
+    if boot_signature && (fat_name(54) || fat_name(82)) {
+        return Some(FsType::Fat);
+    }
+    if LittleFs::mount(image).is_ok() {
+        return Some(FsType::LittleFs);
+    }
+    None
+}
+
+fn extract_fat_dir<T: fatfs::ReadWriteSeek>(
+    dir: &fatfs::Dir<T>,
+    out: &Path,
+    stats: &mut ExtractStats,
+) -> Result<(), std::io::Error> {
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        let path = out.join(&name);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+            stats.dirs += 1;
+            extract_fat_dir(&entry.to_dir(), &path, stats)?;
+        } else {
+            let mut data = Vec::new();
+            entry.to_file().read_to_end(&mut data)?;
+            std::fs::write(&path, &data)?;
+            stats.files += 1;
+            stats.bytes += data.len() as u64;
+        }
+    }
+    Ok(())
+}
+
+/// 将 FAT 或 littlefs 镜像中的文件提取到主机目录 `out`
+pub fn extract_image(image: &[u8], out: &Path) -> Result<ReadFsInfo, std::io::Error> {
+    let Some(fs_type) = detect_fs(image) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "No FAT or littlefs filesystem found in the partition",
+        ));
+    };
+    std::fs::create_dir_all(out)?;
+    let stats = match fs_type {
+        FsType::Fat => {
+            let mut stats = ExtractStats::default();
+            // fatfs 要求存储可写，在副本上挂载
+            let mut cursor = Cursor::new(image.to_vec());
+            let fs = fatfs::FileSystem::new(&mut cursor, fatfs::FsOptions::new())?;
+            extract_fat_dir(&fs.root_dir(), out, &mut stats)?;
+            stats
+        }
+        FsType::LittleFs => LittleFs::mount(image)?.extract(out)?,
+    };
+    Ok(ReadFsInfo { fs_type, stats })
+}
+
+pub trait ReadFsTrait {
+    /// 读取分区并将其中的文件提取到主机目录
+    fn read_fs(&mut self, params: &ReadFsParams) -> Result<ReadFsInfo, std::io::Error>;
+}
+
+impl ReadFsTrait for SifliTool {
+    fn read_fs(&mut self, params: &ReadFsParams) -> Result<ReadFsInfo, std::io::Error> {
+        let image = self.read_flash(params.address, params.size)?;
+        extract_image(&image, Path::new(&params.output))
+    }
+}
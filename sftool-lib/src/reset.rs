@@ -1,5 +1,7 @@
 use crate::ram_command::{Command, RamCommand};
+use crate::ram_stub::StubKind;
 use crate::SifliTool;
+use std::io::Write;
 
 pub trait Reset {
     fn soft_reset(&mut self) -> Result<(), std::io::Error>;
@@ -7,7 +9,13 @@ pub trait Reset {
 
 impl Reset for SifliTool {
     fn soft_reset(&mut self) -> Result<(), std::io::Error> {
+        if self.stub == StubKind::FactoryCali {
+            // 校准 stub 没有 burn_reset，reboot 后不会有应答
+            self.port.write_all(Command::Reboot.to_string().as_bytes())?;
+            self.port.flush()?;
+            return Ok(());
+        }
         self.command(Command::SoftReset)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
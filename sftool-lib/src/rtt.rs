@@ -0,0 +1,163 @@
+use crate::SifliDebugTool;
+use indicatif::ProgressStyle;
+use memmap2::Mmap;
+use phf::phf_map;
+use probe_rs::rtt::{Rtt, ScanRegion};
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// 没有 ELF 文件时在这段 RAM 中搜索 RTT 控制块
+static RTT_SCAN_RANGE: phf::Map<&'static str, (u64, u64)> = phf_map! {
+    "sf32lb52" => (0x2000_0000, 0x2008_0000),
+};
+
+const RTT_SYMBOL: &str = "_SEGGER_RTT";
+// 固件启动后需要一点时间才会初始化控制块
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Default)]
+pub struct RttParams {
+    /// 固件的 ELF 文件，用于查找 `_SEGGER_RTT` 符号
+    pub elf_path: Option<String>,
+}
+
+pub trait RttTrait {
+    /// 输出所有 up 通道的数据，并把标准输入的每一行写入 down 通道 0，直到出错为止
+    fn rtt(&mut self, params: &RttParams) -> Result<(), std::io::Error>;
+}
+
+fn find_rtt_symbol(elf_path: &str) -> Result<Option<u64>, std::io::Error> {
+    let file = File::open(elf_path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let elf = goblin::elf::Elf::parse(&mmap[..])
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    Ok(elf
+        .syms
+        .iter()
+        .find(|sym| elf.strtab.get_at(sym.st_name) == Some(RTT_SYMBOL))
+        .map(|sym| sym.st_value))
+}
+
+impl SifliDebugTool {
+    fn rtt_scan_region(&self, params: &RttParams) -> Result<ScanRegion, std::io::Error> {
+        if let Some(elf_path) = &params.elf_path {
+            match find_rtt_symbol(elf_path)? {
+                Some(address) => return Ok(ScanRegion::Exact(address)),
+                None => tracing::warn!("{} not found in {}, scanning RAM", RTT_SYMBOL, elf_path),
+            }
+        }
+        match RTT_SCAN_RANGE.get(self.base.chip.as_str()) {
+            Some((start, end)) => Ok(ScanRegion::Ranges(vec![*start..*end])),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No RTT scan range known for {}", self.base.chip),
+            )),
+        }
+    }
+}
+
+impl RttTrait for SifliDebugTool {
+    fn rtt(&mut self, params: &RttParams) -> Result<(), std::io::Error> {
+        let region = self.rtt_scan_region(params)?;
+
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+            spinner.set_message("Searching for RTT control block...");
+        }
+
+        let quiet = self.base.quiet;
+        let mut core = self.core()?;
+        let start = Instant::now();
+        let mut rtt = loop {
+            match Rtt::attach_region(&mut core, &region) {
+                Ok(rtt) => break rtt,
+                Err(e) if start.elapsed() < ATTACH_TIMEOUT => {
+                    tracing::debug!("RTT attach failed: {}, retrying", e);
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("RTT control block not found: {}", e),
+                    ));
+                }
+            }
+        };
+
+        if !quiet {
+            let names: Vec<String> = rtt
+                .up_channels()
+                .iter()
+                .map(|ch| format!("{}:{}", ch.number(), ch.name().unwrap_or("?")))
+                .collect();
+            spinner.finish_with_message(format!("RTT attached, up channels [{}]", names.join(", ")));
+        }
+
+        // 标准输入在单独的线程中读取，避免阻塞 up 通道的轮询
+        let (tx, rx) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // 有多个 up 通道时，在切换通道输出时标注通道号
+        let multiple = rtt.up_channels().len() > 1;
+        let mut last_channel = None;
+        let mut stdout = std::io::stdout();
+        let mut buffer = vec![0u8; 1024];
+        loop {
+            let mut received = false;
+            for channel in rtt.up_channels().iter_mut() {
+                let len = channel
+                    .read(&mut core, &mut buffer)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                if len == 0 {
+                    continue;
+                }
+                received = true;
+                if multiple && last_channel != Some(channel.number()) {
+                    write!(stdout, "\n[{}] ", channel.number())?;
+                    last_channel = Some(channel.number());
+                }
+                stdout.write_all(&buffer[..len])?;
+            }
+            stdout.flush()?;
+
+            while let Ok(line) = rx.try_recv() {
+                let Some(channel) = rtt.down_channels().first_mut() else {
+                    tracing::warn!("Target has no RTT down channel, input ignored");
+                    continue;
+                };
+                let data = format!("{}\n", line);
+                let mut data = data.as_bytes();
+                // down 通道缓冲区满时目标每次只会取走一部分
+                while !data.is_empty() {
+                    let written = channel
+                        .write(&mut core, data)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                    data = &data[written..];
+                    if written == 0 {
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                }
+            }
+
+            if !received {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
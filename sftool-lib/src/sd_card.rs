@@ -0,0 +1,489 @@
+use crate::SifliTool;
+use crate::WriteFlashParams;
+use crate::read_fs::{FsType, detect_fs};
+use crate::write_flash::{WriteFlashFile, get_file_crc32, str_to_u32};
+use indicatif::ProgressStyle;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+pub const SECTOR_SIZE: u32 = 512;
+
+// SD stub 在 0x62000000 上找不到 flash 时访问 SD 卡，卡内字节偏移 = 地址 - 0x62000000，
+// burn_erase_write、burn_verify 和 burn_read 都按这个地址换算扇区
+pub const SD_BASE_ADDRESS: u32 = 0x6200_0000;
+/// stub 的地址是 32 位的，只能访问卡的前 `0x100000000 - 0x62000000` 字节（约 2.47 GiB）
+pub const SD_REACHABLE_SIZE: u64 = (1 << 32) - SD_BASE_ADDRESS as u64;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_PROTECTIVE: u8 = 0xEE;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTableKind {
+    Mbr,
+    Gpt,
+}
+
+impl fmt::Display for PartitionTableKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionTableKind::Mbr => write!(f, "MBR"),
+            PartitionTableKind::Gpt => write!(f, "GPT"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Partition {
+    /// 从 1 开始的分区号，与 Linux 的 mmcblk0pN 一致
+    pub index: u32,
+    pub start: u64,
+    pub sectors: u64,
+    /// 分区类型的名称，未知类型显示 MBR 类型号或 GPT 类型 GUID
+    pub kind: String,
+    /// GPT 分区名，MBR 为空
+    pub name: String,
+}
+
+impl Partition {
+    pub fn end(&self) -> u64 {
+        self.start + self.sectors
+    }
+
+    /// 整个分区是否都在 stub 能访问的范围内
+    pub fn reachable(&self) -> bool {
+        self.end() * SECTOR_SIZE as u64 <= SD_REACHABLE_SIZE
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionTable {
+    pub kind: PartitionTableKind,
+    pub partitions: Vec<Partition>,
+}
+
+impl PartitionTable {
+    pub fn partition(&self, index: u32) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.index == index)
+    }
+}
+
+impl fmt::Display for PartitionTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {} partitions", self.kind, self.partitions.len())?;
+        for p in self.partitions.iter() {
+            write!(
+                f,
+                "\n    p{:<2} start {:>10}  {:>10} sectors ({} MB)  {}",
+                p.index,
+                p.start,
+                p.sectors,
+                p.sectors * SECTOR_SIZE as u64 / (1024 * 1024),
+                p.kind
+            )?;
+            if !p.name.is_empty() {
+                write!(f, " \"{}\"", p.name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn mbr_type_name(kind: u8) -> String {
+    match kind {
+        0x01 => "FAT12".to_string(),
+        0x04 | 0x06 | 0x0E => "FAT16".to_string(),
+        0x0B | 0x0C => "FAT32".to_string(),
+        0x07 => "NTFS/exFAT".to_string(),
+        0x05 | 0x0F | 0x85 => "Extended".to_string(),
+        0x82 => "Linux swap".to_string(),
+        0x83 => "Linux".to_string(),
+        0xEF => "EFI System".to_string(),
+        _ => format!("type 0x{:02X}", kind),
+    }
+}
+
+// GUID 的前三段按小端存储
+fn guid_string(guid: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        le_u32(guid, 0),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8],
+        guid[9],
+        guid[10..16]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>()
+    )
+}
+
+fn gpt_type_name(guid: &[u8]) -> String {
+    let guid = guid_string(guid);
+    match guid.as_str() {
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => "EFI System".to_string(),
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Basic data".to_string(),
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux filesystem".to_string(),
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap".to_string(),
+        "21686148-6449-6E6F-744E-656564454649" => "BIOS boot".to_string(),
+        _ => guid,
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// 解析 MBR 或 GPT 分区表，`read(扇区, 扇区数)` 从卡或镜像中读取数据。
+/// 扇区 0 不是分区表（例如直接格式化成 FAT 的卡）时返回 `None`
+pub fn parse_partition_table(
+    read: &mut dyn FnMut(u64, u32) -> Result<Vec<u8>, std::io::Error>,
+) -> Result<Option<PartitionTable>, std::io::Error> {
+    let mbr = read(0, 1)?;
+    if mbr.get(510..512) != Some(&MBR_SIGNATURE[..]) {
+        return Ok(None);
+    }
+    // 没有分区表的卡扇区 0 是文件系统的引导扇区，同样以 0x55AA 结尾
+    let oem = &mbr[3..11];
+    if detect_fs(&mbr) == Some(FsType::Fat) || oem == b"EXFAT   " || oem == b"NTFS    " {
+        return Ok(None);
+    }
+
+    let mut partitions = Vec::new();
+    let mut protective = false;
+    for i in 0..4 {
+        let entry = &mbr[446 + i * 16..462 + i * 16];
+        if entry[0] & 0x7F != 0 {
+            return Ok(None);
+        }
+        let (kind, start, sectors) = (entry[4], le_u32(entry, 8), le_u32(entry, 12));
+        if kind == 0 || sectors == 0 {
+            continue;
+        }
+        if kind == MBR_PROTECTIVE {
+            protective = true;
+            continue;
+        }
+        partitions.push(Partition {
+            index: i as u32 + 1,
+            start: start as u64,
+            sectors: sectors as u64,
+            kind: mbr_type_name(kind),
+            name: String::new(),
+        });
+    }
+    if !protective {
+        if partitions.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(PartitionTable {
+            kind: PartitionTableKind::Mbr,
+            partitions,
+        }));
+    }
+
+    let header = read(1, 1)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Err(invalid("Protective MBR without a GPT header"));
+    }
+    let header_size = le_u32(&header, 12) as usize;
+    if !(92..=SECTOR_SIZE as usize).contains(&header_size) {
+        return Err(invalid("Invalid GPT header size"));
+    }
+    let mut check = header[..header_size].to_vec();
+    check[16..20].fill(0);
+    if GPT_CRC.checksum(&check) != le_u32(&header, 16) {
+        return Err(invalid("GPT header CRC mismatch"));
+    }
+
+    let entries_lba = le_u64(&header, 72);
+    let count = le_u32(&header, 80) as usize;
+    let entry_size = le_u32(&header, 84) as usize;
+    if entry_size < 128 || count * entry_size > 1024 * 1024 {
+        return Err(invalid("Invalid GPT partition entries"));
+    }
+    let entry_sectors = (count * entry_size).div_ceil(SECTOR_SIZE as usize) as u32;
+    let entries = read(entries_lba, entry_sectors)?;
+    if GPT_CRC.checksum(&entries[..count * entry_size]) != le_u32(&header, 88) {
+        return Err(invalid("GPT partition entries CRC mismatch"));
+    }
+
+    let partitions = entries[..count * entry_size]
+        .chunks(entry_size)
+        .enumerate()
+        .filter(|(_, entry)| entry[..16].iter().any(|b| *b != 0))
+        .map(|(i, entry)| {
+            let first = le_u64(entry, 32);
+            let last = le_u64(entry, 40);
+            let name: Vec<u16> = entry[56..128]
+                .chunks(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|c| *c != 0)
+                .collect();
+            Partition {
+                index: i as u32 + 1,
+                start: first,
+                sectors: last.saturating_sub(first) + 1,
+                kind: gpt_type_name(&entry[..16]),
+                name: String::from_utf16_lossy(&name),
+            }
+        })
+        .collect();
+    Ok(Some(PartitionTable {
+        kind: PartitionTableKind::Gpt,
+        partitions,
+    }))
+}
+
+/// 读取主机上磁盘镜像的分区表
+pub fn image_partition_table(file: &File) -> Result<Option<PartitionTable>, std::io::Error> {
+    let len = file.metadata()?.len();
+    if len < 2 * SECTOR_SIZE as u64 {
+        return Ok(None);
+    }
+    let mut file = file;
+    let table = parse_partition_table(&mut |sector, count| {
+        let mut data = vec![0u8; (count * SECTOR_SIZE) as usize];
+        file.seek(SeekFrom::Start(sector * SECTOR_SIZE as u64))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    });
+    file.seek(SeekFrom::Start(0))?;
+    table
+}
+/// 把卡内的字节偏移 `[offset, offset + len)` 换算成 stub 的地址，超出 [`SD_REACHABLE_SIZE`] 时报错
+pub(crate) fn sd_address(offset: u64, len: u64) -> Result<u32, std::io::Error> {
+    // 长度为 0 时起始地址本身也必须能访问
+    if offset + len.max(1) > SD_REACHABLE_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "SD card offset 0x{:X} (0x{:X} bytes) is beyond the 0x{:X} bytes the stub can address",
+                offset, len, SD_REACHABLE_SIZE
+            ),
+        ));
+    }
+    Ok(SD_BASE_ADDRESS + offset as u32)
+}
+
+pub trait SdCardTrait {
+    /// 通过 burn_read 读取 SD 卡或 eMMC 的 MBR/GPT 分区表，卡上没有分区表时返回 `None`。
+    /// stub 没有读取 CID、CSD 和卡容量的命令，因此不提供这些信息
+    fn sd_info(&mut self) -> Result<Option<PartitionTable>, std::io::Error>;
+}
+
+// burn_erase_write 用 0xFF 补齐最后一个扇区，这里事先补齐，使校验的长度和 CRC 与卡上一致
+fn pad_to_sector(file: File) -> Result<File, std::io::Error> {
+    let len = file.metadata()?.len();
+    let padding = len.next_multiple_of(SECTOR_SIZE as u64) - len;
+    if padding == 0 {
+        return Ok(file);
+    }
+    let mut padded = tempfile::tempfile()?;
+    std::io::copy(&mut &file, &mut padded)?;
+    padded.write_all(&vec![0xFFu8; padding as usize])?;
+    padded.seek(SeekFrom::Start(0))?;
+    Ok(padded)
+}
+
+impl SifliTool {
+    fn check_sd(&self) -> Result<(), std::io::Error> {
+        if self.base.memory_type != "sd" {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "SD card commands are only available with --memory sd",
+            ));
+        }
+        Ok(())
+    }
+
+    fn sd_read_sectors(&mut self, sector: u64, count: u32) -> Result<Vec<u8>, std::io::Error> {
+        let len = count * SECTOR_SIZE;
+        let address = sd_address(sector * SECTOR_SIZE as u64, len as u64)?;
+        self.read_chunk(address, len, &|_| {})
+    }
+
+    fn sd_partitions(&mut self) -> Result<Option<PartitionTable>, std::io::Error> {
+        parse_partition_table(&mut |sector, count| self.sd_read_sectors(sector, count))
+    }
+
+    // 解析 `file@address`、`file@pN` 或不带地址的磁盘镜像，`table` 缓存卡上的分区表
+    fn sd_target(
+        &mut self,
+        spec: &str,
+        table: &mut Option<Option<PartitionTable>>,
+    ) -> Result<WriteFlashFile, std::io::Error> {
+        let (path, target) = match spec.split_once('@') {
+            Some((path, target)) => (path, Some(target)),
+            None => (spec, None),
+        };
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        let sector = match target {
+            Some(target) if target.starts_with('p') => {
+                let index: u32 = target[1..].parse().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid partition index: {}", target),
+                    )
+                })?;
+                if table.is_none() {
+                    *table = Some(self.sd_partitions()?);
+                }
+                let Some(partition) = table
+                    .as_ref()
+                    .and_then(|table| table.as_ref())
+                    .and_then(|table| table.partition(index))
+                else {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("Partition {} not found on the SD card", index),
+                    ));
+                };
+                if !partition.reachable() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Partition {} ends beyond the first 0x{:X} bytes of the card that the stub can address",
+                            index, SD_REACHABLE_SIZE
+                        ),
+                    ));
+                }
+                if partition.start + len.div_ceil(SECTOR_SIZE as u64) > partition.end() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} ({} bytes) does not fit in partition {} ({} sectors)",
+                            path, len, index, partition.sectors
+                        ),
+                    ));
+                }
+                partition.start
+            }
+            Some(target) => {
+                let address = str_to_u32(target)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+                if address % SECTOR_SIZE != 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "SD card address 0x{:08X} must be a multiple of the {} byte sector size",
+                            address, SECTOR_SIZE
+                        ),
+                    ));
+                }
+                (address / SECTOR_SIZE) as u64
+            }
+            None => {
+                // 不带地址时只接受带有分区表的整盘镜像，从扇区 0 开始写入
+                if image_partition_table(&file)?.is_none() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} is not a disk image, please use the <file@address> or <file@pN> format",
+                            path
+                        ),
+                    ));
+                }
+                0
+            }
+        };
+
+        let file = pad_to_sector(file)?;
+        let address = sd_address(sector * SECTOR_SIZE as u64, file.metadata()?.len())?;
+        let crc32 = get_file_crc32(&file)?;
+        Ok(WriteFlashFile {
+            address,
+            file,
+            crc32,
+        })
+    }
+
+    /// 把 SD 卡的 `write_flash` 参数换算成 stub 的地址：地址是卡内的字节偏移，
+    /// 也可以用 `@pN` 写入第 N 个分区，带有 MBR/GPT 的磁盘镜像可以不指定地址
+    pub(crate) fn sd_write_files(
+        &mut self,
+        params: &WriteFlashParams,
+    ) -> Result<Vec<WriteFlashFile>, std::io::Error> {
+        if params.fw_verify_code.is_some() || params.public_key.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Signature checks and the firmware verify code are not supported on SD cards",
+            ));
+        }
+        let mut table = None;
+        params
+            .file_path
+            .iter()
+            .map(|spec| self.sd_target(spec, &mut table))
+            .collect()
+    }
+}
+
+impl SdCardTrait for SifliTool {
+    fn sd_info(&mut self) -> Result<Option<PartitionTable>, std::io::Error> {
+        self.check_sd()?;
+
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", self.step));
+            self.step = self.step.wrapping_add(1);
+            spinner.set_message("Reading the SD card partition table...");
+        }
+
+        let table = self.sd_partitions()?;
+
+        if !self.base.quiet {
+            match table {
+                Some(ref table) => spinner.finish_with_message(format!("Found {}", table.kind)),
+                None => spinner.finish_with_message("No partition table found"),
+            }
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(start: u64, sectors: u64) -> Partition {
+        Partition {
+            index: 1,
+            start,
+            sectors,
+            kind: String::new(),
+            name: String::new(),
+        }
+    }
+
+    #[test]
+    fn reachable_range() {
+        let last_sector = SD_REACHABLE_SIZE / SECTOR_SIZE as u64;
+        assert!(partition(2048, last_sector - 2048).reachable());
+        assert!(!partition(2048, last_sector - 2047).reachable());
+
+        assert_eq!(sd_address(0, 512).unwrap(), SD_BASE_ADDRESS);
+        assert_eq!(
+            sd_address(SD_REACHABLE_SIZE - 512, 512).unwrap(),
+            u32::MAX - 511
+        );
+        assert!(sd_address(SD_REACHABLE_SIZE - 512, 1024).is_err());
+        assert!(sd_address(SD_REACHABLE_SIZE, 0).is_err());
+    }
+}
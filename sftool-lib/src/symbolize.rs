@@ -0,0 +1,167 @@
+use goblin::elf::section_header::SHT_NOBITS;
+use goblin::elf::sym::STT_FUNC;
+use std::rc::Rc;
+
+type DwarfReader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+struct Function {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+/// 根据固件 ELF 的符号表和 DWARF 行号信息，把日志中的地址解析为 `函数+偏移 (文件:行)`
+pub struct Symbolizer {
+    functions: Vec<Function>,
+    /// ELF 中没有调试信息时为 `None`，此时只输出函数名
+    context: Option<addr2line::Context<DwarfReader>>,
+}
+
+impl Symbolizer {
+    pub fn new(elf_path: &str) -> Result<Self, std::io::Error> {
+        let data = std::fs::read(elf_path)?;
+        let elf = goblin::elf::Elf::parse(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+        let mut functions: Vec<Function> = elf
+            .syms
+            .iter()
+            .filter(|sym| sym.st_type() == STT_FUNC && sym.st_value != 0)
+            .filter_map(|sym| {
+                Some(Function {
+                    // Thumb 函数地址的最低位为 1
+                    address: sym.st_value & !1,
+                    size: sym.st_size,
+                    name: elf.strtab.get_at(sym.st_name)?.to_string(),
+                })
+            })
+            .collect();
+        functions.sort_by_key(|f| f.address);
+
+        let endian = if elf.little_endian {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
+        let has_debug_info = elf
+            .section_headers
+            .iter()
+            .any(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".debug_info"));
+        let context = if has_debug_info {
+            let dwarf = gimli::Dwarf::load(|id| -> Result<DwarfReader, gimli::Error> {
+                let section = elf.section_headers.iter().find(|sh| {
+                    sh.sh_type != SHT_NOBITS && elf.shdr_strtab.get_at(sh.sh_name) == Some(id.name())
+                });
+                let bytes: Rc<[u8]> = match section {
+                    Some(sh) => data
+                        .get(sh.sh_offset as usize..(sh.sh_offset + sh.sh_size) as usize)
+                        .unwrap_or_default()
+                        .into(),
+                    None => Rc::from(&[][..]),
+                };
+                Ok(gimli::EndianRcSlice::new(bytes, endian))
+            })
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            match addr2line::Context::from_dwarf(dwarf) {
+                Ok(context) => Some(context),
+                Err(e) => {
+                    tracing::warn!("Failed to load DWARF from {}: {}", elf_path, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Self { functions, context })
+    }
+
+    fn function(&self, address: u64) -> Option<&Function> {
+        let index = self
+            .functions
+            .partition_point(|f| f.address <= address)
+            .checked_sub(1)?;
+        let function = &self.functions[index];
+        let in_range = if function.size == 0 {
+            address == function.address
+        } else {
+            address < function.address + function.size
+        };
+        in_range.then_some(function)
+    }
+
+    /// 解析单个地址，不在任何函数内的地址返回 `None`
+    pub fn lookup(&self, address: u64) -> Option<String> {
+        let address = address & !1;
+        let function = self.function(address)?;
+        let mut result = format!("{}+0x{:x}", function.name, address - function.address);
+
+        let location = self
+            .context
+            .as_ref()
+            .and_then(|context| context.find_location(address).ok().flatten());
+        if let Some(location) = location {
+            if let (Some(file), Some(line)) = (location.file, location.line) {
+                result.push_str(&format!(" ({}:{})", file, line));
+            }
+        }
+        Some(result)
+    }
+
+    /// 找出一行日志中所有能解析的地址，返回 `(地址, 解析结果)`
+    pub fn symbolize_line(&self, line: &str) -> Vec<(u64, String)> {
+        let mut results: Vec<(u64, String)> = Vec::new();
+        for address in find_addresses(line) {
+            if results.iter().any(|(a, _)| *a == address) {
+                continue;
+            }
+            if let Some(symbol) = self.lookup(address) {
+                results.push((address, symbol));
+            }
+        }
+        results
+    }
+}
+
+// 匹配 `0x` 开头或单独出现的 8 位十六进制数，例如 `PC: 0x1202a3c5`、`lr 1202a3c5`
+fn find_addresses(line: &str) -> Vec<u64> {
+    let mut addresses = Vec::new();
+    for token in line.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let hex = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .unwrap_or(token);
+        if hex.len() == 8 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            if let Ok(address) = u64::from_str_radix(hex, 16) {
+                addresses.push(address);
+            }
+        }
+    }
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_inside_text() {
+        assert_eq!(find_addresses("PC: 0x1202a3c5"), vec![0x1202_A3C5]);
+        assert_eq!(
+            find_addresses("lr 1202A3C5,sp=0X20001000)"),
+            vec![0x1202_A3C5, 0x2000_1000]
+        );
+        assert_eq!(find_addresses("[0x12020100]hard fault"), vec![0x1202_0100]);
+    }
+
+    #[test]
+    fn near_miss_hex_tokens() {
+        // 位数不是 8 位
+        assert!(find_addresses("0x1202a3c tick 1202a3c5f").is_empty());
+        // 不是十六进制，或与字母数字连在一起
+        assert!(find_addresses("1202g3c5 x1202a3c5 0x1202a3c5u").is_empty());
+        // 带分隔符的数字会被拆开
+        assert!(find_addresses("0x1202_a3c5").is_empty());
+        assert!(find_addresses("").is_empty());
+    }
+}
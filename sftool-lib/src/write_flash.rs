@@ -1,7 +1,9 @@
-use crate::SifliTool;
-use crate::ram_command::{Command, RamCommand, Response};
+use crate::{SifliTool, WriteFlashParams};
+use crate::flash_id::FlashIdTrait;
+use crate::ram_command::{Command, RamCommand, Response, parse_hex_output};
+use crate::sign_image;
 use crc::Algorithm;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::ProgressStyle;
 use lazy_static::lazy_static;
 use memmap2::Mmap;
 use phf::phf_map;
@@ -15,24 +17,27 @@ use tempfile::tempfile;
 
 const ELF_MAGIC: &[u8] = &[0x7F, 0x45, 0x4C, 0x46]; // ELF file magic number
 
+// stub 的 fw_enc_wr 最多写入 32 字节的固件校验码
+pub const FW_VERIFY_CODE_MAX: usize = 32;
+
 pub trait WriteFlashTrait {
     fn write_flash(&mut self) -> Result<(), std::io::Error>;
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-enum FileType {
+pub(crate) enum FileType {
     Bin,
     Hex,
     Elf,
 }
 
-struct WriteFlashFile {
-    address: u32,
-    file: File,
-    crc32: u32,
+pub(crate) struct WriteFlashFile {
+    pub(crate) address: u32,
+    pub(crate) file: File,
+    pub(crate) crc32: u32,
 }
 
-fn str_to_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+pub(crate) fn str_to_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
     if let Some(hex_digits) = s.strip_prefix("0x") {
         u32::from_str_radix(hex_digits, 16)
     } else if let Some(bin_digits) = s.strip_prefix("0b") {
@@ -44,7 +49,7 @@ fn str_to_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
     }
 }
 
-fn detect_file_type(path: &Path) -> Result<FileType, std::io::Error> {
+pub(crate) fn detect_file_type(path: &Path) -> Result<FileType, std::io::Error> {
     if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
         match ext.to_lowercase().as_str() {
             "bin" => return Ok(FileType::Bin),
@@ -206,19 +211,21 @@ fn elf_to_bin(elf_file: &Path) -> Result<Vec<WriteFlashFile>, std::io::Error> {
     Ok(write_flash_files)
 }
 
-fn get_file_crc32(file: &File) -> Result<u32, std::io::Error> {
-    const CRC_32_ALGO: Algorithm<u32> = Algorithm {
-        width: 32,
-        poly: 0x04C11DB7,
-        init: 0,
-        refin: true,
-        refout: true,
-        xorout: 0,
-        check: 0x2DFD2D88,
-        residue: 0,
-    };
-
-    const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&CRC_32_ALGO);
+const CRC_32_ALGO: Algorithm<u32> = Algorithm {
+    width: 32,
+    poly: 0x04C11DB7,
+    init: 0,
+    refin: true,
+    refout: true,
+    xorout: 0,
+    check: 0x2DFD2D88,
+    residue: 0,
+};
+
+/// stub 的 `burn_verify` 使用的 CRC
+pub(crate) const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&CRC_32_ALGO);
+
+pub(crate) fn get_file_crc32(file: &File) -> Result<u32, std::io::Error> {
     let mut reader = BufReader::new(file);
 
     let mut digest = CRC.digest();
@@ -238,7 +245,7 @@ fn get_file_crc32(file: &File) -> Result<u32, std::io::Error> {
 }
 
 lazy_static! {
-    static ref CHIP_MEMORY_LAYOUT: HashMap<&'static str, Vec<u32>> = {
+    pub(crate) static ref CHIP_MEMORY_LAYOUT: HashMap<&'static str, Vec<u32>> = {
         let mut m = HashMap::new();
         m.insert("sf32lb52", vec![0x10000000, 0x12000000]);
         m
@@ -251,7 +258,7 @@ impl SifliTool {
         write_flash_files: &[WriteFlashFile],
         step: &mut i32,
     ) -> Result<(), std::io::Error> {
-        let spinner = ProgressBar::new_spinner();
+        let spinner = self.base.new_spinner();
         if !self.base.quiet {
             spinner.enable_steady_tick(std::time::Duration::from_millis(100));
             spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
@@ -275,8 +282,160 @@ impl SifliTool {
         Ok(())
     }
 
+    // 签名是主机端的分离签名，校验的是命令行给出的原始文件，芯片本身不参与
+    fn check_signatures(
+        &mut self,
+        file_paths: &[String],
+        public_key: Option<&str>,
+        require_signed: bool,
+        step: &mut i32,
+    ) -> Result<(), std::io::Error> {
+        let Some(public_key) = public_key else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "A public key is required to verify signed images",
+            ));
+        };
+        let key = sign_image::load_public_key(public_key)?;
+
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", step));
+            spinner.set_message("Verifying image signatures...");
+            *step = step.wrapping_add(1);
+        }
+        for file in file_paths.iter() {
+            let path = file.split('@').next().unwrap_or(file);
+            let signature_path = sign_image::signature_path(path);
+            let signature = match std::fs::read(&signature_path) {
+                Ok(signature) => signature,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    if require_signed {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::PermissionDenied,
+                            format!("{} is not signed, {} not found", path, signature_path),
+                        ));
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let data = std::fs::read(path)?;
+            sign_image::verify_image(&data, &signature, &key)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        }
+        if !self.base.quiet {
+            spinner.finish_with_message("Image signatures verified");
+        }
+        Ok(())
+    }
+
+    // 检查镜像是否超出 flash 的实际容量，超出部分会回绕覆盖 flash 开头的数据。
+    // 容量来自 stub 按 JEDEC ID 识别的参数，读不到时不写入
+    fn check_flash_size(&mut self, write_flash_files: &[WriteFlashFile]) -> Result<(), std::io::Error> {
+        let mut sizes: HashMap<u32, u64> = HashMap::new();
+        for f in write_flash_files.iter() {
+            let base = f.address & 0xFF00_0000;
+            let size = match sizes.get(&base) {
+                Some(size) => *size,
+                None => {
+                    let Some(details) = self.flash_details(base)? else {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("No flash detected at 0x{:08X}", base),
+                        ));
+                    };
+                    sizes.insert(base, details.size());
+                    details.size()
+                }
+            };
+            let end = (f.address - base) as u64 + f.file.metadata()?.len();
+            if end > size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Image at 0x{:08X} ends at 0x{:08X}, beyond the {} KB flash at 0x{:08X}",
+                        f.address,
+                        base as u64 + end,
+                        size / 1024,
+                        base
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // 读取 OTP 出厂配置中的固件校验码（FACTORY_CFG_ID_FWVERIFY），stub 固定读取 32 字节
+    fn read_fw_verify_code(&mut self) -> Result<Vec<u8>, std::io::Error> {
+        let output = self.command_output(
+            Command::OtpFwencRead,
+            &["otp_fwenc_read:", "otp_fwenc_read error"],
+        )?;
+        if output.contains("otp_fwenc_read error") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Failed to read the firmware verify code from OTP",
+            ));
+        }
+        parse_hex_output(&output, "otp_fwenc_read:", "\n")
+            .filter(|code| code.len() == FW_VERIFY_CODE_MAX)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid firmware verify code read from OTP",
+                )
+            })
+    }
+
+    // 通过 stub 的 fw_enc_wr 写入固件校验码，stub 写入后会回读比较；已经一致时跳过
+    fn write_fw_verify_code(&mut self, code: &[u8], step: &mut i32) -> Result<(), std::io::Error> {
+        if code.is_empty() || code.len() > FW_VERIFY_CODE_MAX {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "The firmware verify code must be 1 to {} bytes",
+                    FW_VERIFY_CODE_MAX
+                ),
+            ));
+        }
+        let spinner = self.base.new_spinner();
+        if !self.base.quiet {
+            spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+            spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
+            spinner.set_prefix(format!("0x{:02X}", step));
+            spinner.set_message("Writing the firmware verify code to OTP...");
+            *step = step.wrapping_add(1);
+        }
+        if self.read_fw_verify_code()?.starts_with(code) {
+            if !self.base.quiet {
+                spinner.finish_with_message("Firmware verify code already written, skip!");
+            }
+            return Ok(());
+        }
+        let output = self.command_output(
+            Command::FwEncWrite {
+                len: code.len() as u32,
+                code: code.iter().map(|b| format!("{:02x}", b)).collect(),
+            },
+            &["FW_VERIFYCODE_WRITE_PASS", "FW_VERIFYCODE_WRITE_FAIL"],
+        )?;
+        if !output.contains("FW_VERIFYCODE_WRITE_PASS") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Failed to write the firmware verify code to OTP",
+            ));
+        }
+        if !self.base.quiet {
+            spinner.finish_with_message("Firmware verify code written");
+        }
+        Ok(())
+    }
+
     fn verify(&mut self, address: u32, len: u32, crc: u32, step: &mut i32) -> Result<(), std::io::Error> {
-        let spinner = ProgressBar::new_spinner();
+        let spinner = self.base.new_spinner();
         if !self.base.quiet {
             spinner.enable_steady_tick(std::time::Duration::from_millis(100));
             spinner.set_style(ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap());
@@ -300,7 +459,6 @@ impl SifliTool {
 
 impl WriteFlashTrait for SifliTool {
     fn write_flash(&mut self) -> Result<(), std::io::Error> {
-        let mut step = self.step;
         let params = self
             .write_flash_params
             .as_ref()
@@ -309,59 +467,90 @@ impl WriteFlashTrait for SifliTool {
                 std::io::ErrorKind::InvalidInput,
                 "No write flash params",
             ))?;
+        self.write_flash_with(&params)
+    }
+}
+
+impl SifliTool {
+    /// 按给定的参数执行 `write_flash` 的流程，供内部生成镜像后写入，不改动 `write_flash_params`
+    pub(crate) fn write_flash_with(
+        &mut self,
+        params: &WriteFlashParams,
+    ) -> Result<(), std::io::Error> {
+        let mut step = self.step;
+        if params.public_key.is_some() || params.require_signed {
+            self.check_signatures(
+                &params.file_path,
+                params.public_key.as_deref(),
+                params.require_signed,
+                &mut step,
+            )?;
+        }
+
         let mut write_flash_files: Vec<WriteFlashFile> = Vec::new();
 
         let packet_size = if self.base.compat { 256 } else { 128 * 1024 };
 
-        for file in params.file_path.iter() {
-            // file@address
-            let parts: Vec<_> = file.split('@').collect();
-            // 如果存在@符号，则证明是bin文件
-            if parts.len() == 2 {
-                let addr = str_to_u32(parts[1])
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
-                let file = File::open(parts[0])?;
-                let crc32 = get_file_crc32(&file.try_clone()?)?;
-                write_flash_files.push(WriteFlashFile {
-                    address: addr,
-                    file,
-                    crc32,
-                });
-                continue;
-            }
+        // SD 卡的地址是卡内的字节偏移，换算成 stub 的地址后与 flash 一样写入和校验
+        if self.base.memory_type == "sd" {
+            write_flash_files = self.sd_write_files(params)?;
+        } else {
+            for file in params.file_path.iter() {
+                // file@address
+                let parts: Vec<_> = file.split('@').collect();
+                // 如果存在@符号，则证明是bin文件
+                if parts.len() == 2 {
+                    let addr = str_to_u32(parts[1])
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+                    let file = File::open(parts[0])?;
+                    let crc32 = get_file_crc32(&file.try_clone()?)?;
+                    write_flash_files.push(WriteFlashFile {
+                        address: addr,
+                        file,
+                        crc32,
+                    });
+                    continue;
+                }
 
-            let file_type = detect_file_type(Path::new(parts[0]))?;
+                let file_type = detect_file_type(Path::new(parts[0]))?;
 
-            match file_type {
-                FileType::Hex => {
-                    write_flash_files.append(&mut hex_to_bin(Path::new(parts[0]))?);
-                }
-                FileType::Elf => {
-                    write_flash_files.append(&mut elf_to_bin(Path::new(parts[0]))?);
-                }
-                FileType::Bin => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidInput,
-                        "For binary files, please use the <file@address> format",
-                    ));
+                match file_type {
+                    FileType::Hex => {
+                        write_flash_files.append(&mut hex_to_bin(Path::new(parts[0]))?);
+                    }
+                    FileType::Elf => {
+                        write_flash_files.append(&mut elf_to_bin(Path::new(parts[0]))?);
+                    }
+                    FileType::Bin => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            "For binary files, please use the <file@address> format",
+                        ));
+                    }
                 }
             }
         }
 
-        if params.erase_all {
+        if self.base.memory_type == "nor" || self.base.memory_type == "nand" {
+            self.check_flash_size(&write_flash_files)?;
+        }
+
+        // SD 卡由控制器管理擦除，不需要整片擦除
+        let erase_all = params.erase_all && self.base.memory_type != "sd";
+        if erase_all {
             self.erase_all(&write_flash_files, &mut step)?;
         }
 
         for file in write_flash_files.iter() {
-            let re_download_spinner = ProgressBar::new_spinner();
-            let download_bar = ProgressBar::new(file.file.metadata()?.len());
+            let re_download_spinner = self.base.new_spinner();
+            let download_bar = self.base.new_progress_bar(file.file.metadata()?.len());
 
             let download_bar_template = ProgressStyle::default_bar()
                 .template("[{prefix}] Download at {msg}... {wide_bar} {bytes_per_sec} {percent_precise}%")
                 .unwrap()
                 .progress_chars("=>-");
 
-            if !params.erase_all {
+            if !erase_all {
                 if !self.base.quiet {
                     re_download_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
                     re_download_spinner.set_style(
@@ -415,10 +604,7 @@ impl WriteFlashTrait for SifliTool {
                     }
                     let res = self.send_data(&buffer[..bytes_read])?;
                     if res == Response::RxWait {
-                        if !self.base.quiet {
-                            download_bar.inc(bytes_read as u64);
-                            // downloaded += bytes_read;
-                        }
+                        download_bar.inc(bytes_read as u64);
                         continue;
                     } else if res != Response::Ok {
                         return Err(std::io::Error::new(
@@ -465,9 +651,7 @@ impl WriteFlashTrait for SifliTool {
                         ));
                     }
                     address += bytes_read as u32;
-                    if !self.base.quiet {
-                        download_bar.inc(bytes_read as u64);
-                    }
+                    download_bar.inc(bytes_read as u64);
                 }
                 if !self.base.quiet {
                     download_bar.finish_with_message("Download success!");
@@ -478,6 +662,11 @@ impl WriteFlashTrait for SifliTool {
                 self.verify(file.address, file.file.metadata()?.len() as u32, file.crc32, &mut step)?;
             }
         }
+
+        // 镜像全部写入成功后再写 OTP，避免写入失败时留下与 flash 内容不符的校验码
+        if let Some(ref code) = params.fw_verify_code {
+            self.write_fw_verify_code(code, &mut step)?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,384 @@
+use crate::flash_id::FlashIdTrait;
+use crate::littlefs::build_littlefs_image;
+use crate::read_fs::FsType;
+use crate::{SifliTool, WriteFlashParams};
+use std::fmt;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// FAT 的逻辑扇区大小：NOR 与擦除扇区一致，NAND 与页大小一致，SD 卡为 512 字节
+pub fn sector_size(memory_type: &str) -> Result<u16, std::io::Error> {
+    match memory_type {
+        "nor" => Ok(4096),
+        "nand" => Ok(2048),
+        "sd" => Ok(512),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unknown memory type: {}", memory_type),
+        )),
+    }
+}
+
+/// littlefs 的默认块大小，与各存储类型的擦除单位一致：NOR 为 4KB 扇区，NAND 为 128KB 块
+pub fn block_size(memory_type: &str) -> Result<u32, std::io::Error> {
+    match memory_type {
+        "nor" => Ok(4096),
+        "nand" => Ok(128 * 1024),
+        "sd" => Ok(512),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unknown memory type: {}", memory_type),
+        )),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FsImageInfo {
+    /// FAT12/16/32 或 littlefs
+    pub fs_type: String,
+    /// FAT 的扇区大小或 littlefs 的块大小
+    pub block_size: u32,
+    pub size: u32,
+    pub files: usize,
+    pub dirs: usize,
+    /// 所有文件的总大小
+    pub bytes: u64,
+    /// 镜像中 `used` 之后的部分无需写入：FAT 为最后一个非全零扇区之后，
+    /// littlefs 为最后一个已分配的块之后
+    pub used: u32,
+}
+
+impl fmt::Display for FsImageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = if self.fs_type.starts_with("FAT") {
+            "sectors"
+        } else {
+            "blocks"
+        };
+        write!(
+            f,
+            "{} image: {} KB, {} byte {}, {} files in {} directories ({} bytes)",
+            self.fs_type,
+            self.size / 1024,
+            self.block_size,
+            unit,
+            self.files,
+            self.dirs,
+            self.bytes
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MkfsImageParams {
+    /// 主机上的源目录
+    pub source: String,
+    pub output: String,
+    pub fs_type: FsType,
+    /// 分区大小，FAT 要求是扇区大小的整数倍
+    pub size: u32,
+    /// FAT 使用的扇区大小
+    pub sector_size: u16,
+    /// littlefs 使用的块大小和块数，块数默认占满整个分区
+    pub block_size: u32,
+    pub block_count: Option<u32>,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteFsParams {
+    pub source: String,
+    pub fs_type: FsType,
+    /// 分区起始地址和大小
+    pub address: u32,
+    pub size: u32,
+    /// littlefs 的块大小，默认读取存储器的擦除块大小
+    pub block_size: Option<u32>,
+    pub block_count: Option<u32>,
+    pub label: Option<String>,
+}
+
+fn volume_label(label: Option<&str>) -> Result<[u8; 11], std::io::Error> {
+    let label = label.unwrap_or("NO NAME");
+    if label.len() > 11 || !label.is_ascii() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The volume label must be at most 11 ASCII characters",
+        ));
+    }
+    let mut bytes = [b' '; 11];
+    bytes[..label.len()].copy_from_slice(label.to_ascii_uppercase().as_bytes());
+    Ok(bytes)
+}
+
+// 递归复制目录，按文件名排序使生成的镜像可复现
+fn copy_dir<T: fatfs::ReadWriteSeek>(
+    source: &Path,
+    dir: &fatfs::Dir<T>,
+    info: &mut FsImageInfo,
+) -> Result<(), std::io::Error> {
+    let mut entries = std::fs::read_dir(source)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid file name: {:?}", entry.path()),
+            ));
+        };
+        let in_fs = |e: std::io::Error| {
+            std::io::Error::new(e.kind(), format!("{}: {}", entry.path().display(), e))
+        };
+        let metadata = std::fs::metadata(entry.path())?;
+        if metadata.is_dir() {
+            let sub_dir = dir.create_dir(name).map_err(in_fs)?;
+            info.dirs += 1;
+            copy_dir(&entry.path(), &sub_dir, info)?;
+        } else if metadata.is_file() {
+            let data = std::fs::read(entry.path())?;
+            let mut file = dir.create_file(name).map_err(in_fs)?;
+            file.truncate().map_err(in_fs)?;
+            file.write_all(&data).map_err(in_fs)?;
+            info.files += 1;
+            info.bytes += data.len() as u64;
+        }
+    }
+    Ok(())
+}
+
+/// 用主机目录中的文件生成 FAT 镜像，FAT12/16/32 根据分区大小自动选择
+pub fn build_fat_image(
+    source: &Path,
+    size: u32,
+    sector_size: u16,
+    label: Option<&str>,
+) -> Result<(Vec<u8>, FsImageInfo), std::io::Error> {
+    if !source.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} is not a directory", source.display()),
+        ));
+    }
+    if size == 0 || size % sector_size as u32 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "The partition size must be a multiple of the {} byte sector size",
+                sector_size
+            ),
+        ));
+    }
+
+    let mut image = Cursor::new(vec![0u8; size as usize]);
+    fatfs::format_volume(
+        &mut image,
+        fatfs::FormatVolumeOptions::new()
+            .bytes_per_sector(sector_size)
+            .total_sectors(size / sector_size as u32)
+            .volume_label(volume_label(label)?),
+    )?;
+    image.set_position(0);
+
+    let mut info = FsImageInfo {
+        fs_type: String::new(),
+        block_size: sector_size as u32,
+        size,
+        files: 0,
+        dirs: 0,
+        bytes: 0,
+        used: 0,
+    };
+    {
+        let fs = fatfs::FileSystem::new(&mut image, fatfs::FsOptions::new())?;
+        info.fs_type = format!("{:?}", fs.fat_type()).to_uppercase();
+        copy_dir(source, &fs.root_dir(), &mut info)?;
+        fs.unmount()?;
+    }
+
+    let image = image.into_inner();
+    let last = image.iter().rposition(|b| *b != 0).unwrap_or(0) as u32;
+    info.used = (last / sector_size as u32 + 1) * sector_size as u32;
+    Ok((image, info))
+}
+
+// 按分区大小和块大小生成 littlefs 镜像，littlefs 没有卷标
+fn build_littlefs_partition(
+    source: &Path,
+    size: u32,
+    block_size: u32,
+    block_count: Option<u32>,
+    label: Option<&str>,
+) -> Result<(Vec<u8>, FsImageInfo), std::io::Error> {
+    if label.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "littlefs does not support a volume label",
+        ));
+    }
+    if block_size == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The block size must not be zero",
+        ));
+    }
+    let block_count = block_count.unwrap_or(size / block_size);
+    if block_count as u64 * block_size as u64 > size as u64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} blocks of {} bytes do not fit in the 0x{:X} byte partition",
+                block_count, block_size, size
+            ),
+        ));
+    }
+    build_littlefs_image(source, block_size, block_count)
+}
+
+/// 离线生成 FAT 或 littlefs 镜像并保存到文件
+pub fn mkfs_image(params: &MkfsImageParams) -> Result<FsImageInfo, std::io::Error> {
+    let (image, info) = match params.fs_type {
+        FsType::Fat => build_fat_image(
+            Path::new(&params.source),
+            params.size,
+            params.sector_size,
+            params.label.as_deref(),
+        )?,
+        FsType::LittleFs => build_littlefs_partition(
+            Path::new(&params.source),
+            params.size,
+            params.block_size,
+            params.block_count,
+            params.label.as_deref(),
+        )?,
+    };
+    std::fs::write(&params.output, image)?;
+    Ok(info)
+}
+
+pub trait WriteFsTrait {
+    /// 生成 FAT 或 littlefs 镜像并写入分区。FAT 使用当前存储类型的扇区大小，
+    /// littlefs 未指定块大小时使用存储器的擦除块大小
+    fn write_fs(&mut self, params: &WriteFsParams) -> Result<FsImageInfo, std::io::Error>;
+}
+
+impl SifliTool {
+    // 使用 stub 识别到的擦除单位：NOR 为扇区大小，NAND 为块大小
+    pub(crate) fn erase_block_size(&mut self, address: u32) -> Result<u32, std::io::Error> {
+        match self.base.memory_type.as_str() {
+            "nor" | "nand" => match self.flash_details(address)? {
+                Some(details) => Ok(details.info.sector_size),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("No flash detected at 0x{:08X}", address & 0xFF00_0000),
+                )),
+            },
+            memory_type => block_size(memory_type),
+        }
+    }
+}
+
+impl WriteFsTrait for SifliTool {
+    fn write_fs(&mut self, params: &WriteFsParams) -> Result<FsImageInfo, std::io::Error> {
+        let (image, info) = match params.fs_type {
+            FsType::Fat => build_fat_image(
+                Path::new(&params.source),
+                params.size,
+                sector_size(&self.base.memory_type)?,
+                params.label.as_deref(),
+            )?,
+            FsType::LittleFs => {
+                let block_size = match params.block_size {
+                    Some(block_size) => block_size,
+                    None => self.erase_block_size(params.address)?,
+                };
+                build_littlefs_partition(
+                    Path::new(&params.source),
+                    params.size,
+                    block_size,
+                    params.block_count,
+                    params.label.as_deref(),
+                )?
+            }
+        };
+
+        // FAT 中未使用的簇在 FAT 表中已标记为空闲，littlefs 中未使用的块不在任何元数据对的链表上，
+        // 都只需写入到 `used` 为止
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(&image[..info.used as usize])?;
+        file.flush()?;
+
+        // 复用 write_flash 的流程，包括跳过未变化的数据、容量检查和校验
+        self.write_flash_with(&WriteFlashParams {
+            file_path: vec![format!(
+                "{}@0x{:08X}",
+                file.path().display(),
+                params.address
+            )],
+            verify: true,
+            no_compress: false,
+            erase_all: false,
+            public_key: None,
+            require_signed: false,
+            fw_verify_code: None,
+        })?;
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn read_file<T: fatfs::ReadWriteSeek>(dir: &fatfs::Dir<T>, path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        dir.open_file(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    fn source_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("boot.txt"), b"hello").unwrap();
+        std::fs::create_dir_all(dir.path().join("assets/fonts")).unwrap();
+        let font = (0..10000u32).map(|i| i as u8).collect::<Vec<_>>();
+        std::fs::write(dir.path().join("assets/fonts/long file name.ttf"), font).unwrap();
+        std::fs::write(dir.path().join("assets/empty.bin"), b"").unwrap();
+        dir
+    }
+
+    #[test]
+    fn fat_image_round_trip() {
+        let source = source_dir();
+        for (size, sector_size) in [
+            (1024 * 1024, 4096u16),
+            (512 * 1024, 2048),
+            (64 * 1024 * 1024, 512),
+        ] {
+            let (image, info) =
+                build_fat_image(source.path(), size, sector_size, Some("res")).unwrap();
+            assert_eq!(image.len(), size as usize);
+            assert_eq!((info.files, info.dirs, info.bytes), (3, 2, 10005));
+            assert!(info.used <= size && info.used % sector_size as u32 == 0);
+            assert!(image[info.used as usize..].iter().all(|b| *b == 0));
+
+            let mut image = Cursor::new(image);
+            let fs = fatfs::FileSystem::new(&mut image, fatfs::FsOptions::new()).unwrap();
+            assert_eq!(format!("{:?}", fs.fat_type()).to_uppercase(), info.fs_type);
+            assert_eq!(fs.volume_label(), "RES");
+            let root = fs.root_dir();
+            assert_eq!(read_file(&root, "boot.txt"), b"hello");
+            let font = read_file(&root, "assets/fonts/long file name.ttf");
+            assert_eq!(font, (0..10000u32).map(|i| i as u8).collect::<Vec<_>>());
+            assert!(read_file(&root, "assets/empty.bin").is_empty());
+        }
+    }
+
+    #[test]
+    fn fat_image_rejects_bad_size() {
+        let source = source_dir();
+        assert!(build_fat_image(source.path(), 4096 * 100 + 512, 4096, None).is_err());
+        assert!(build_fat_image(source.path(), 0, 512, None).is_err());
+        assert!(build_fat_image(source.path(), 64 * 1024, 512, Some("label too long")).is_err());
+    }
+}
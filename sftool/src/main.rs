@@ -1,8 +1,31 @@
+use sftool_lib::chip_info::ChipInfoTrait;
+use sftool_lib::core_status::CoreStatusTrait;
+use sftool_lib::dump_mem::{DumpFormat, DumpMemParams, DumpMemTrait};
+use sftool_lib::efuse::{EfuseTarget, EfuseTrait};
+use sftool_lib::factory_cali::{
+    BatteryCaliParams, CrystalCaliParams, FactoryCaliParams, FactoryCaliTrait,
+};
+use sftool_lib::gdb_server::{GdbServerParams, GdbServerTrait};
+use sftool_lib::load_ram::{LoadRamParams, LoadRamTrait};
+use sftool_lib::memory::{MemoryTrait, MemoryWidth, format_hexdump};
+use sftool_lib::monitor::{MonitorExit, MonitorParams, MonitorTrait, monitor_port};
+use sftool_lib::nvds::{NvdsKey, NvdsLayout, NvdsParams, NvdsTrait};
+use sftool_lib::read_fs::{FsType, ReadFsParams, ReadFsTrait};
 use sftool_lib::reset::Reset;
-use clap::{Parser, Subcommand, ValueEnum};
+use sftool_lib::rtt::{RttParams, RttTrait};
+use sftool_lib::sd_card::SdCardTrait;
+use sftool_lib::sign_image::{SignImageParams, sign_image};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use sftool_lib::ports::expand_port_pattern;
 use sftool_lib::write_flash::WriteFlashTrait;
+use sftool_lib::write_fs::{
+    MkfsImageParams, WriteFsParams, WriteFsTrait, block_size, mkfs_image, sector_size,
+};
 use sftool_lib::speed::SpeedTrait;
-use sftool_lib::{SifliTool, SifliToolBase, WriteFlashParams};
+use sftool_lib::{AUTO_DETECT, SifliDebugTool, SifliTool, SifliToolBase, WriteFlashParams};
+use std::path::Path;
+use std::time::Duration;
 use strum::{Display, EnumString};
 
 #[derive(EnumString, Display, Debug, Clone, ValueEnum)]
@@ -29,20 +52,121 @@ enum Operation {
     SoftReset,
 }
 
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
+enum AfterOperation {
+    #[clap(name = "no_reset")]
+    None,
+    #[clap(name = "soft_reset")]
+    SoftReset,
+    /// Soft reset, then stream RTT output like the `rtt` command
+    #[clap(name = "rtt")]
+    Rtt,
+    /// Soft reset, then open a serial monitor like the `monitor` command
+    #[clap(name = "monitor")]
+    Monitor,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Width {
+    #[clap(name = "8")]
+    W8,
+    #[clap(name = "16")]
+    W16,
+    #[clap(name = "32")]
+    W32,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    #[clap(name = "raw")]
+    Raw,
+    #[clap(name = "elf")]
+    Elf,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Filesystem {
+    #[clap(name = "fat")]
+    Fat,
+    #[clap(name = "littlefs")]
+    LittleFs,
+}
+
+impl From<Filesystem> for FsType {
+    fn from(fs: Filesystem) -> Self {
+        match fs {
+            Filesystem::Fat => FsType::Fat,
+            Filesystem::LittleFs => FsType::LittleFs,
+        }
+    }
+}
+
+impl From<Format> for DumpFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Raw => DumpFormat::Raw,
+            Format::Elf => DumpFormat::Elf,
+        }
+    }
+}
+
+impl From<Width> for MemoryWidth {
+    fn from(width: Width) -> Self {
+        match width {
+            Width::W8 => MemoryWidth::Byte,
+            Width::W16 => MemoryWidth::HalfWord,
+            Width::W32 => MemoryWidth::Word,
+        }
+    }
+}
+
+fn parse_u32(s: &str) -> Result<u32, String> {
+    let res = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u32::from_str_radix(&hex.replace('_', ""), 16)
+    } else {
+        s.replace('_', "").parse::<u32>()
+    };
+    res.map_err(|e| format!("invalid number '{}': {}", s, e))
+}
+
+fn parse_partition(s: &str) -> Result<(u32, u32), String> {
+    let (address, size) = s
+        .split_once(':')
+        .ok_or_else(|| format!("invalid partition '{}', expected <address:size>", s))?;
+    Ok((parse_u32(address)?, parse_u32(size)?))
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    let bytes: Option<Vec<u8>> = if hex.len() % 2 == 0 {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+            .collect()
+    } else {
+        None
+    };
+    bytes.ok_or_else(|| format!("invalid hex value '{}'", s))
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about = "sftool CLI", long_about = None)]
 struct Cli {
-    /// Target chip type
+    /// Target chip type. When omitted, the only supported chip (SF32LB52) is used after checking its ID register
     #[arg(short = 'c', long = "chip", value_enum)]
-    chip: Chip,
+    chip: Option<Chip>,
 
-    /// Memory type
-    #[arg(short = 'm', long = "memory", value_enum, default_value = "nor")]
-    memory: Memory,
+    /// Memory type. When omitted, NOR or NAND is taken from the flash the stub finds; SD cards must be specified
+    #[arg(short = 'm', long = "memory", value_enum)]
+    memory: Option<Memory>,
 
-    /// Serial port device
+    /// Serial port device, or a `usb:VID:PID[:SERIAL]` / `serial:SERIAL` selector that survives replugging.
+    /// May be given multiple times or as a glob pattern (e.g. /dev/ttyUSB*) to operate on several boards in parallel
     #[arg(short = 'p', long = "port")]
-    port: String,
+    port: Vec<String>,
 
     /// Serial port baud rate used when flashing/reading
     #[arg(short = 'b', long = "baud", default_value = "1000000")]
@@ -54,7 +178,7 @@ struct Cli {
 
     /// What to do after siflitool is finished
     #[arg(long = "after", value_enum, default_value = "soft_reset")]
-    after: Operation,
+    after: AfterOperation,
 
     /// Number of attempts to connect, negative or 0 for infinite. Default: 3.
     #[arg(long = "connect-attempts", default_value_t = 3)]
@@ -65,6 +189,14 @@ struct Cli {
     #[arg(long = "compat")]
     compat: bool,
 
+    /// Baud rate of the application, used by `monitor` and `--after monitor`
+    #[arg(long = "monitor-baud", default_value = "1000000")]
+    monitor_baud: u32,
+
+    /// Prefix each monitor line with the time since the monitor started
+    #[arg(long = "timestamps")]
+    timestamps: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -74,6 +206,90 @@ enum Commands {
     /// Write a binary blob to flash
     #[command(name = "write_flash")]
     WriteFlash(WriteFlash),
+
+    /// List serial ports and the SiFli UART probes found on them
+    #[command(name = "list_ports")]
+    ListPorts(ListPorts),
+
+    /// Show chip ID, revision, UID and the detected flash
+    #[command(name = "chip_info")]
+    ChipInfo,
+
+    /// Read memory or peripheral registers over the UART debug link
+    #[command(name = "read_mem")]
+    ReadMem(ReadMem),
+
+    /// Write memory or a peripheral register over the UART debug link
+    #[command(name = "write_mem")]
+    WriteMem(WriteMem),
+
+    /// Save a RAM or memory-mapped flash range to a file without resetting the chip
+    #[command(name = "dump_mem")]
+    DumpMem(DumpMem),
+
+    /// Load an image into RAM and run it without touching flash
+    #[command(name = "load_ram")]
+    LoadRam(LoadRam),
+
+    /// Stream RTT output from the running firmware, stdin is sent to down channel 0
+    #[command(name = "rtt")]
+    Rtt(RttArgs),
+
+    /// Open a serial monitor at the application baud rate
+    #[command(name = "monitor")]
+    Monitor(MonitorArgs),
+
+    /// Run a GDB server on a local TCP port over the UART debug link
+    #[command(name = "gdb_server")]
+    GdbServer(GdbServer),
+
+    /// Halt the core and show its registers and decoded fault status
+    #[command(name = "core_status")]
+    CoreStatus(CoreStatusArgs),
+
+    /// Read eFuse fields
+    #[command(name = "efuse_read")]
+    EfuseRead(EfuseRead),
+
+    /// Burn eFuse bits (irreversible)
+    #[command(name = "efuse_write")]
+    EfuseWrite(EfuseWrite),
+
+    /// Show the MBR/GPT partition table of the SD card/eMMC (the stub cannot report CID, CSD or capacity)
+    #[command(name = "sd_info")]
+    SdInfo,
+
+    /// List all records of an NVDS key-value config region
+    #[command(name = "config_dump")]
+    ConfigDump(ConfigDump),
+
+    /// Read one key from an NVDS key-value config region
+    #[command(name = "config_get")]
+    ConfigGet(ConfigGet),
+
+    /// Set one key in an NVDS key-value config region, rewriting only the affected erase blocks
+    #[command(name = "config_set")]
+    ConfigSet(ConfigSet),
+
+    /// Build a FAT or littlefs image from a host directory and write it to a partition
+    #[command(name = "write_fs")]
+    WriteFs(WriteFs),
+
+    /// Read a FAT or littlefs partition and extract its files to a host directory
+    #[command(name = "read_fs")]
+    ReadFs(ReadFs),
+
+    /// Build a FAT or littlefs image from a host directory without connecting (offline)
+    #[command(name = "mkfs_image")]
+    MkfsImage(MkfsImage),
+
+    /// Create a detached signature for an image, checked by write_flash on the host (offline)
+    #[command(name = "sign_image")]
+    SignImage(SignImage),
+
+    /// Run crystal and battery calibration with the factory calibration stub
+    #[command(name = "factory_cali")]
+    FactoryCali(FactoryCali),
 }
 
 #[derive(Parser, Debug)]
@@ -91,48 +307,961 @@ struct WriteFlash {
     #[arg(short = 'e', long = "erase-all")]
     erase_all: bool,
 
-    /// Binary file (format: <filename@address>, if file format includes address info, @address is optional)
+    /// Public key (PEM) used to verify the <file>.sig signatures created by sign_image before writing
+    #[arg(long = "public-key")]
+    public_key: Option<String>,
+
+    /// Refuse to write images without a <file>.sig signature
+    #[arg(long = "require-signed", requires = "public_key")]
+    require_signed: bool,
+
+    /// After writing, store this firmware verify code (hex, up to 32 bytes) in the OTP factory
+    /// configuration with the stub's fw_enc_wr. Encrypted images must be created with the SDK tools
+    #[arg(long = "fw-verify-code")]
+    fw_verify_code: Option<String>,
+
+    /// Confirm writing the firmware verify code, required because OTP writes cannot be undone
+    #[arg(long = "confirm", requires = "fw_verify_code")]
+    confirm: bool,
+
+    /// Binary file (format: <filename@address>, if file format includes address info, @address is optional).
+    /// With --memory sd the address is a byte offset on the card, <filename@pN> writes to partition N,
+    /// and MBR/GPT disk images need no address. Only the first 0x9E000000 bytes (about 2.47 GiB) of the
+    /// card are reachable through the stub
     #[arg(required = true)]
     files: Vec<String>,
 }
 
+#[derive(Parser, Debug)]
+#[command(about = "List serial ports and the SiFli UART probes found on them")]
+struct ListPorts {
+    /// Only show known USB-serial adapters (CH340/CH343/CP210x...) and ports usable by sftool
+    #[arg(long = "known")]
+    known: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Read memory or peripheral registers over the UART debug link")]
+struct ReadMem {
+    /// Access width in bits
+    #[arg(short = 'w', long = "width", value_enum, default_value = "32")]
+    width: Width,
+
+    /// Start address
+    #[arg(value_parser = parse_u32)]
+    address: u32,
+
+    /// Number of items of the given width to read
+    #[arg(value_parser = parse_u32, default_value = "1")]
+    count: u32,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Write memory or a peripheral register over the UART debug link")]
+struct WriteMem {
+    /// Access width in bits
+    #[arg(short = 'w', long = "width", value_enum, default_value = "32")]
+    width: Width,
+
+    /// Target address
+    #[arg(value_parser = parse_u32)]
+    address: u32,
+
+    /// Value to write
+    #[arg(value_parser = parse_u32)]
+    value: u32,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Save a RAM or memory-mapped flash range to a file without resetting the chip")]
+struct DumpMem {
+    /// Output format, `elf` writes an ELF core file with a single PT_LOAD segment
+    #[arg(short = 'f', long = "format", value_enum, default_value = "raw")]
+    format: Format,
+
+    /// Start address
+    #[arg(value_parser = parse_u32)]
+    address: u32,
+
+    /// Number of bytes to dump
+    #[arg(value_parser = parse_u32)]
+    length: u32,
+
+    /// Output file
+    file: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Load an image into RAM and run it without touching flash")]
+struct LoadRam {
+    /// ELF file, or binary file and its load address as <FILE@ADDRESS>
+    #[arg(required = true)]
+    file: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Stream RTT output from the running firmware, stdin is sent to down channel 0")]
+struct RttArgs {
+    /// Firmware ELF file used to locate the `_SEGGER_RTT` control block, RAM is scanned otherwise
+    #[arg(long = "elf")]
+    elf: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Run a GDB server on a local TCP port over the UART debug link")]
+struct GdbServer {
+    /// Local TCP port to listen on
+    #[arg(long = "gdb-port", default_value = "3333")]
+    gdb_port: u16,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Halt the core and show its registers and decoded fault status")]
+struct CoreStatusArgs {
+    /// Resume the core after reading its status
+    #[arg(long = "resume")]
+    resume: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Read eFuse fields")]
+struct EfuseRead {
+    /// Field name or BITOFF:BYTES, all known fields are shown when omitted
+    field: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Burn eFuse bits (irreversible)")]
+struct EfuseWrite {
+    /// Field name or BITOFF:BYTES
+    field: String,
+
+    /// New value of the whole field in hex, bytes in eFuse order
+    value: String,
+
+    /// Only show which bits would be burned
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// Confirm burning the bits, required because eFuse writes cannot be undone
+    #[arg(long = "confirm")]
+    confirm: bool,
+}
+
+#[derive(Args, Debug)]
+struct NvdsRegion {
+    /// NVDS region start address and size (format: <address:size>)
+    #[arg(long = "region", value_parser = parse_partition, required = true)]
+    region: (u32, u32),
+
+    /// Magic bytes at the start of the region in hex, defaults to 5344564E. The default layout is
+    /// not taken from the SDK, check it against the target firmware before writing real boards
+    #[arg(long = "magic")]
+    magic: Option<String>,
+
+    /// Alignment of the records in bytes
+    #[arg(long = "align", default_value = "4")]
+    align: usize,
+}
+
+impl NvdsRegion {
+    fn params(&self) -> Result<NvdsParams, std::io::Error> {
+        let mut layout = NvdsLayout {
+            align: self.align,
+            ..NvdsLayout::default()
+        };
+        if let Some(ref magic) = self.magic {
+            layout.magic = parse_hex(magic)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        }
+        Ok(NvdsParams {
+            address: self.region.0,
+            size: self.region.1,
+            layout,
+        })
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "List all records of an NVDS key-value config region")]
+struct ConfigDump {
+    #[command(flatten)]
+    region: NvdsRegion,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Read one key from an NVDS key-value config region")]
+struct ConfigGet {
+    #[command(flatten)]
+    region: NvdsRegion,
+
+    /// Key name (bd_addr, device_name) or tag number
+    key: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Set one key in an NVDS key-value config region, rewriting only the affected erase blocks")]
+struct ConfigSet {
+    #[command(flatten)]
+    region: NvdsRegion,
+
+    /// Key name (bd_addr, device_name) or tag number
+    key: String,
+
+    /// New value: AA:BB:CC:DD:EE:FF for bd_addr, a string for device_name, hex bytes otherwise
+    value: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Build a FAT or littlefs image from a host directory and write it to a partition")]
+struct WriteFs {
+    /// Partition start address and size (format: <address:size>)
+    #[arg(long = "partition", value_parser = parse_partition, required = true)]
+    partition: (u32, u32),
+
+    /// Filesystem to create
+    #[arg(long = "fs", value_enum, default_value = "fat")]
+    fs: Filesystem,
+
+    /// littlefs block size, defaults to the erase block size reported by the flash
+    #[arg(long = "block-size", value_parser = parse_u32)]
+    block_size: Option<u32>,
+
+    /// littlefs block count, defaults to filling the partition
+    #[arg(long = "block-count", value_parser = parse_u32)]
+    block_count: Option<u32>,
+
+    /// Volume label, at most 11 characters (FAT only)
+    #[arg(long = "label")]
+    label: Option<String>,
+
+    /// Host directory whose contents become the root of the filesystem
+    dir: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Read a FAT or littlefs partition and extract its files to a host directory")]
+struct ReadFs {
+    /// Partition start address
+    #[arg(value_parser = parse_u32)]
+    address: u32,
+
+    /// Partition size
+    #[arg(value_parser = parse_u32)]
+    size: u32,
+
+    /// Host directory the files are extracted to
+    output: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Build a FAT or littlefs image from a host directory without connecting (offline)")]
+struct MkfsImage {
+    /// Partition size, the sector size follows --memory
+    #[arg(long = "size", value_parser = parse_u32, required = true)]
+    size: u32,
+
+    /// Output image file
+    #[arg(short = 'o', long = "output", required = true)]
+    output: String,
+
+    /// Filesystem to create
+    #[arg(long = "fs", value_enum, default_value = "fat")]
+    fs: Filesystem,
+
+    /// littlefs block size, defaults to the erase block size of --memory
+    /// (nor: 4096, nand: 131072, sd: 512)
+    #[arg(long = "block-size", value_parser = parse_u32)]
+    block_size: Option<u32>,
+
+    /// littlefs block count, defaults to filling the partition
+    #[arg(long = "block-count", value_parser = parse_u32)]
+    block_count: Option<u32>,
+
+    /// Volume label, at most 11 characters (FAT only)
+    #[arg(long = "label")]
+    label: Option<String>,
+
+    /// Host directory whose contents become the root of the filesystem
+    dir: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Create a detached signature for an image, checked by write_flash on the host (offline)")]
+struct SignImage {
+    /// Private key in PEM format (P-256 or RSA-2048)
+    #[arg(short = 'k', long = "key", required = true)]
+    key: String,
+
+    /// Signature file, defaults to <input>.sig
+    #[arg(short = 'o', long = "output")]
+    output: Option<String>,
+
+    /// Binary image to sign
+    input: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Run crystal and battery calibration with the factory calibration stub")]
+struct FactoryCali {
+    /// Skip crystal calibration
+    #[arg(long = "no-crystal")]
+    no_crystal: bool,
+
+    /// Pin number the reference clock is fed into, e.g. 8 for PB08
+    #[arg(long = "crystal-pin", default_value = "8")]
+    crystal_pin: u32,
+
+    /// Allowed crystal frequency error in ppm
+    #[arg(long = "crystal-ppm", default_value = "5")]
+    crystal_ppm: u32,
+
+    /// Crystal calibration timeout in seconds
+    #[arg(long = "crystal-timeout", default_value = "20")]
+    crystal_timeout: u32,
+
+    /// Skip battery voltage calibration
+    #[arg(long = "no-battery")]
+    no_battery: bool,
+
+    /// Reference voltage applied to the battery pin in mV
+    #[arg(long = "battery-voltage", default_value = "4000")]
+    battery_voltage: u32,
+
+    /// Maximum deviation of the uncalibrated reading from the reference in mV
+    #[arg(long = "battery-range", default_value = "400")]
+    battery_range: u32,
+
+    /// Allowed error after calibration in mV
+    #[arg(long = "battery-tolerance", default_value = "10")]
+    battery_tolerance: u32,
+
+    /// Upper resistor of the battery voltage divider in kOhm
+    #[arg(long = "battery-r1", default_value = "1000")]
+    battery_r1: u32,
+
+    /// Lower resistor of the battery voltage divider in kOhm
+    #[arg(long = "battery-r2", default_value = "220")]
+    battery_r2: u32,
+
+    /// Write the results as JSON to this file instead of stdout.
+    /// With several ports the port name is inserted before the extension
+    #[arg(short = 'o', long = "output")]
+    output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Open a serial monitor at the application baud rate")]
+struct MonitorArgs {
+    /// Firmware ELF file used to decode addresses in the log into function and source line
+    #[arg(long = "elf")]
+    elf: Option<String>,
+}
+
+fn list_ports(known: bool) -> Result<(), std::io::Error> {
+    let (ports, probes) = sftool_lib::ports::list_ports(known)?;
+    if ports.is_empty() {
+        println!("No serial ports found");
+    } else {
+        println!(
+            "{:<16} {:<9} {:<16} {:<32} {:<8} {}",
+            "PORT", "VID:PID", "SERIAL", "DESCRIPTION", "ADAPTER", "--port"
+        );
+        for port in ports.iter() {
+            let vid_pid = match (port.vid, port.pid) {
+                (Some(vid), Some(pid)) => format!("{:04x}:{:04x}", vid, pid),
+                _ => "-".to_string(),
+            };
+            println!(
+                "{:<16} {:<9} {:<16} {:<32} {:<8} {}",
+                port.port_name,
+                vid_pid,
+                port.serial_number.as_deref().unwrap_or("-"),
+                port.description.as_deref().unwrap_or("-"),
+                port.adapter.unwrap_or("-"),
+                port.port_arg().unwrap_or("- (no SiFli UART probe)"),
+            );
+        }
+    }
+    if !probes.is_empty() {
+        println!();
+        println!("Other probes:");
+        for probe in probes.iter() {
+            println!(
+                "  {} ({:04x}:{:04x}) serial: {}",
+                probe.identifier,
+                probe.vendor_id,
+                probe.product_id,
+                probe.serial_number.as_deref().unwrap_or("-")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn base_param(args: &Cli, port: &str, progress: Option<&ProgressBar>) -> SifliToolBase {
+    SifliToolBase {
+        port_name: port.to_string(),
+        chip: args
+            .chip
+            .as_ref()
+            .map_or(AUTO_DETECT.to_string(), |chip| chip.to_string().to_lowercase()),
+        memory_type: args
+            .memory
+            .as_ref()
+            .map_or(AUTO_DETECT.to_string(), |memory| memory.to_string().to_lowercase()),
+        quiet: progress.is_some(),
+        progress: progress.cloned(),
+        connect_attempts: args.connect_attempts,
+        baud: args.baud,
+        compat: args.compat,
+    }
+}
+
+// 只需要调试接口的命令，不下载 stub，也不会复位芯片
+fn run_debug(
+    args: &Cli,
+    port: &str,
+    progress: Option<&ProgressBar>,
+    stage: &dyn Fn(&str),
+) -> Result<(), std::io::Error> {
+    stage("Connecting...");
+    let mut debug_tool = SifliDebugTool::new(base_param(args, port, progress))?;
+
+    match args.command {
+        Some(Commands::ReadMem(ref read_mem)) => {
+            let values = debug_tool.read_mem(
+                read_mem.address as u64,
+                read_mem.count as usize,
+                read_mem.width.into(),
+            )?;
+            print!(
+                "{}",
+                format_hexdump(read_mem.address as u64, read_mem.width.into(), &values)
+            );
+            Ok(())
+        }
+        Some(Commands::WriteMem(ref write_mem)) => debug_tool.write_mem(
+            write_mem.address as u64,
+            write_mem.value,
+            write_mem.width.into(),
+        ),
+        Some(Commands::DumpMem(ref dump_mem)) => {
+            stage("Dumping memory...");
+            debug_tool.dump_mem(&DumpMemParams {
+                address: dump_mem.address,
+                length: dump_mem.length,
+                file_path: dump_mem.file.clone(),
+                format: dump_mem.format.into(),
+            })
+        }
+        Some(Commands::LoadRam(ref load_ram)) => {
+            stage("Loading RAM image...");
+            debug_tool.load_ram(&LoadRamParams {
+                file_path: load_ram.file.clone(),
+            })
+        }
+        Some(Commands::Rtt(ref rtt)) => debug_tool.rtt(&RttParams {
+            elf_path: rtt.elf.clone(),
+        }),
+        Some(Commands::GdbServer(ref gdb_server)) => debug_tool.gdb_server(&GdbServerParams {
+            port: gdb_server.gdb_port,
+        }),
+        Some(Commands::CoreStatus(ref core_status)) => debug_tool
+            .core_status(core_status.resume)
+            .map(|report| println!("{}", report)),
+        _ => Ok(()),
+    }
+}
+
+fn run(
+    args: &Cli,
+    port: &str,
+    progress: Option<&ProgressBar>,
+    stage: &dyn Fn(&str),
+) -> Result<(), std::io::Error> {
+    if matches!(
+        args.command,
+        Some(Commands::ReadMem(_))
+            | Some(Commands::WriteMem(_))
+            | Some(Commands::DumpMem(_))
+            | Some(Commands::LoadRam(_))
+            | Some(Commands::Rtt(_))
+            | Some(Commands::GdbServer(_))
+            | Some(Commands::CoreStatus(_))
+    ) {
+        return run_debug(args, port, progress, stage);
+    }
+
+    if let Some(Commands::Monitor(ref monitor)) = args.command {
+        let params = MonitorParams {
+            elf_path: monitor.elf.clone(),
+            ..monitor_params(args, false)
+        };
+        return monitor_port(port, &params).map(|_| ());
+    }
+
+    if let Some(Commands::FactoryCali(ref factory_cali)) = args.command {
+        return run_factory_cali(args, port, progress, stage, factory_cali);
+    }
+
+    // 在监视器中按下 Ctrl+R 后重新执行整个下载流程
+    loop {
+        stage("Connecting and downloading stub...");
+        let mut siflitool = SifliTool::try_new(
+            base_param(args, port, progress),
+            if let Some(Commands::WriteFlash(ref write_flash)) = args.command {
+                let fw_verify_code = match write_flash.fw_verify_code {
+                    Some(_) if !write_flash.confirm => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::PermissionDenied,
+                            "OTP writes cannot be undone, re-run with --confirm to store the firmware verify code",
+                        ));
+                    }
+                    Some(ref code) => Some(parse_hex(code).map_err(|e| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+                    })?),
+                    None => None,
+                };
+                Some(WriteFlashParams {
+                    file_path: write_flash.files.clone(),
+                    verify: write_flash.verify,
+                    no_compress: write_flash.no_compress,
+                    erase_all: write_flash.erase_all,
+                    public_key: write_flash.public_key.clone(),
+                    require_signed: write_flash.require_signed,
+                    fw_verify_code,
+                })
+            } else {
+                None
+            },
+        )?;
+
+        if args.baud != 1000000 {
+            siflitool.set_speed(args.baud)?;
+        }
+
+        let res = match args.command {
+            Some(Commands::WriteFlash(_)) => {
+                stage("Writing flash...");
+                siflitool.write_flash()
+            }
+            Some(Commands::WriteFs(ref write_fs)) => {
+                stage("Writing filesystem...");
+                siflitool
+                    .write_fs(&WriteFsParams {
+                        source: write_fs.dir.clone(),
+                        fs_type: write_fs.fs.into(),
+                        address: write_fs.partition.0,
+                        size: write_fs.partition.1,
+                        block_size: write_fs.block_size,
+                        block_count: write_fs.block_count,
+                        label: write_fs.label.clone(),
+                    })
+                    .map(|info| println!("{}", info))
+            }
+            Some(Commands::ReadFs(ref read_fs)) => {
+                stage("Reading filesystem...");
+                siflitool
+                    .read_fs(&ReadFsParams {
+                        address: read_fs.address,
+                        size: read_fs.size,
+                        output: read_fs.output.clone(),
+                    })
+                    .map(|info| println!("{}", info))
+            }
+            Some(Commands::ChipInfo) => siflitool.chip_info().map(|info| println!("{}", info)),
+            Some(Commands::EfuseRead(ref efuse_read)) => run_efuse_read(&mut siflitool, efuse_read),
+            Some(Commands::SdInfo) => siflitool.sd_info().map(|table| match table {
+                Some(table) => println!("{}", table),
+                None => println!("No partition table"),
+            }),
+            Some(Commands::ConfigDump(ref dump)) => dump
+                .region
+                .params()
+                .and_then(|params| siflitool.config_dump(&params))
+                .map(|nvds| println!("{}", nvds)),
+            Some(Commands::ConfigGet(ref get)) => NvdsKey::parse(&get.key).and_then(|key| {
+                let value = siflitool.config_get(&get.region.params()?, key)?;
+                println!("{}", key.format_value(&value));
+                Ok(())
+            }),
+            Some(Commands::ConfigSet(ref set)) => NvdsKey::parse(&set.key).and_then(|key| {
+                let value = key.parse_value(&set.value)?;
+                let blocks = siflitool.config_set(&set.region.params()?, key, &value)?;
+                if blocks.is_empty() {
+                    println!("{} is unchanged", key);
+                } else {
+                    let list: Vec<String> =
+                        blocks.iter().map(|a| format!("0x{:08X}", a)).collect();
+                    println!("{} updated, rewrote {}", key, list.join(", "));
+                }
+                Ok(())
+            }),
+            Some(Commands::EfuseWrite(ref efuse_write)) => {
+                run_efuse_write(&mut siflitool, efuse_write)
+            }
+            _ => Ok(()),
+        };
+
+        if args.after != AfterOperation::None {
+            reset_after(&mut siflitool, stage);
+        }
+
+        match args.after {
+            AfterOperation::Rtt => {
+                res?;
+                // 释放 stub 占用的串口，再通过调试接口连接正在运行的固件
+                drop(siflitool);
+                let mut debug_tool = SifliDebugTool::new(base_param(args, port, progress))?;
+                return debug_tool.rtt(&RttParams {
+                    elf_path: flashed_elf(args),
+                });
+            }
+            AfterOperation::Monitor => {
+                res?;
+                match siflitool.monitor(&monitor_params(args, true))? {
+                    MonitorExit::Reflash => continue,
+                    MonitorExit::Quit => return Ok(()),
+                }
+            }
+            _ => return res,
+        }
+    }
+}
+
+fn run_efuse_read(siflitool: &mut SifliTool, args: &EfuseRead) -> Result<(), std::io::Error> {
+    let targets: Vec<EfuseTarget> = match &args.field {
+        Some(field) => vec![EfuseTarget::parse(field)?],
+        None => siflitool
+            .efuse_fields()
+            .iter()
+            .map(|field| EfuseTarget::Field(field.name.to_string()))
+            .collect(),
+    };
+    for target in targets.iter() {
+        let (field, data) = siflitool.efuse_read(target)?;
+        let value: String = data.iter().map(|b| format!("{:02X}", b)).collect();
+        println!(
+            "{:<12} bit {:>4}  {:>2} bytes  {}  {}",
+            field.name, field.bit_offset, field.bytes, value, field.description
+        );
+    }
+    Ok(())
+}
+
+fn run_efuse_write(siflitool: &mut SifliTool, args: &EfuseWrite) -> Result<(), std::io::Error> {
+    let value = parse_hex(&args.value)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let plan = siflitool.efuse_plan(&EfuseTarget::parse(&args.field)?, &value)?;
+    println!("{}", plan);
+    if args.dry_run {
+        return Ok(());
+    }
+    if !args.confirm {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "eFuse writes cannot be undone, re-run with --confirm to burn the bits above",
+        ));
+    }
+    siflitool.efuse_write(&plan)
+}
+
+fn run_factory_cali(
+    args: &Cli,
+    port: &str,
+    progress: Option<&ProgressBar>,
+    stage: &dyn Fn(&str),
+    cali: &FactoryCali,
+) -> Result<(), std::io::Error> {
+    stage("Connecting and downloading calibration stub...");
+    let mut siflitool = SifliTool::try_new_factory_cali(base_param(args, port, progress))?;
+
+    stage("Calibrating...");
+    let report = siflitool.factory_cali(&FactoryCaliParams {
+        crystal: (!cali.no_crystal).then(|| CrystalCaliParams {
+            pin: cali.crystal_pin,
+            ppm: cali.crystal_ppm,
+            timeout: cali.crystal_timeout,
+        }),
+        battery: (!cali.no_battery).then(|| BatteryCaliParams {
+            voltage: cali.battery_voltage,
+            range: cali.battery_range,
+            tolerance: cali.battery_tolerance,
+            r1: cali.battery_r1,
+            r2: cali.battery_r2,
+        }),
+    })?;
+
+    let json = serde_json::to_string_pretty(&report)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    match cali.output {
+        Some(ref output) => {
+            // 多个端口并行校准时（此时每个端口有单独的进度条），每个端口写入单独的文件
+            let path = if progress.is_some() {
+                let path = Path::new(output);
+                let name = port.rsplit(['/', '\\']).next().unwrap_or(port);
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("factory_cali");
+                let file_name = match path.extension().and_then(|e| e.to_str()) {
+                    Some(ext) => format!("{}_{}.{}", stem, name, ext),
+                    None => format!("{}_{}", stem, name),
+                };
+                path.with_file_name(file_name)
+            } else {
+                Path::new(output).to_path_buf()
+            };
+            std::fs::write(path, json + "\n")?;
+        }
+        None => println!("{}", json),
+    }
+
+    if args.after != AfterOperation::None {
+        reset_after(&mut siflitool, stage);
+    }
+
+    if !report.passed {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Factory calibration failed",
+        ));
+    }
+    Ok(())
+}
+
+// 命令执行完后再复位，复位失败只给出警告，不覆盖命令本身的结果
+fn reset_after(siflitool: &mut SifliTool, stage: &dyn Fn(&str)) {
+    stage("Resetting...");
+    if let Err(e) = siflitool.soft_reset() {
+        eprintln!("Warning: failed to reset the chip: {}", e);
+    }
+}
+
+fn monitor_params(args: &Cli, reflash: bool) -> MonitorParams {
+    MonitorParams {
+        baud: args.monitor_baud,
+        timestamps: args.timestamps,
+        reflash,
+        elf_path: flashed_elf(args),
+    }
+}
+
+// write_flash 中不带地址的 ELF 文件，用于 `--after rtt` 查找 RTT 控制块以及监视器解析地址
+fn flashed_elf(args: &Cli) -> Option<String> {
+    let Some(Commands::WriteFlash(ref write_flash)) = args.command else {
+        return None;
+    };
+    write_flash
+        .files
+        .iter()
+        .find(|file| {
+            !file.contains('@')
+                && Path::new(file)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "elf" | "axf"))
+        })
+        .cloned()
+}
+
+fn run_parallel(args: &Cli, ports: &[String]) -> bool {
+    let multi = MultiProgress::new();
+    let width = ports.iter().map(|p| p.len()).max().unwrap_or(0);
+    let style = ProgressStyle::with_template("[{prefix}] {spinner} {msg}").unwrap();
+
+    let results: Vec<(String, Result<(), String>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = ports
+            .iter()
+            .map(|port| {
+                let bar = multi.add(ProgressBar::new_spinner());
+                bar.set_style(style.clone());
+                bar.set_prefix(format!("{:width$}", port, width = width));
+                bar.enable_steady_tick(Duration::from_millis(100));
+                let style = &style;
+                let handle = scope.spawn(move || {
+                    // 写入和读取时 sftool-lib 把这个进度条换成字节进度，进入下一个阶段时换回来
+                    let res = run(args, port, Some(&bar), &|msg| {
+                        bar.set_style(style.clone());
+                        bar.set_message(msg.to_string());
+                    });
+                    bar.set_style(style.clone());
+                    match &res {
+                        Ok(()) => bar.finish_with_message("PASS"),
+                        Err(e) => bar.finish_with_message(format!("FAIL: {}", e)),
+                    }
+                    res
+                });
+                (port, handle)
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|(port, handle)| {
+                let res = match handle.join() {
+                    Ok(res) => res.map_err(|e| e.to_string()),
+                    Err(_) => Err("panicked".to_string()),
+                };
+                (port.clone(), res)
+            })
+            .collect()
+    });
+
+    let passed = results.iter().filter(|(_, res)| res.is_ok()).count();
+    println!();
+    println!("Summary: {} passed, {} failed", passed, results.len() - passed);
+    for (port, res) in results.iter() {
+        match res {
+            Ok(()) => println!("  {:width$}  PASS", port, width = width),
+            Err(e) => println!("  {:width$}  FAIL  {}", port, e, width = width),
+        }
+    }
+    passed == results.len()
+}
+
 fn main() {
     let args = Cli::parse();
-    let mut siflitool = SifliTool::new(
-        SifliToolBase {
-            port_name: args.port.clone(),
-            chip: args.chip.to_string().to_lowercase(),
-            memory_type: args.memory.to_string().to_lowercase(),
-            quiet: false,
-            connect_attempts: args.connect_attempts,
-            baud: args.baud,
-            compat: args.compat,
-        },
-        if let Some(Commands::WriteFlash(ref write_flash)) = args.command {
-            Some(WriteFlashParams {
-                file_path: write_flash.files.clone(),
-                verify: write_flash.verify,
-                no_compress: write_flash.no_compress,
-                erase_all: write_flash.erase_all,
+
+    // 多个端口并行下载时会启动工作线程，必须在此之前设置环境变量
+    unsafe {
+        sftool_lib::enable_sifli_uart_debug();
+    }
+
+    if let Some(Commands::ListPorts(ref list)) = args.command {
+        if let Err(e) = list_ports(list.known) {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Commands::MkfsImage(ref mkfs)) = args.command {
+        let res = args
+            .memory
+            .as_ref()
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "mkfs_image needs --memory to choose the sector size",
+            ))
+            .and_then(|memory| {
+                let memory_type = memory.to_string().to_lowercase();
+                Ok((
+                    sector_size(&memory_type)?,
+                    match mkfs.block_size {
+                        Some(size) => size,
+                        None => block_size(&memory_type)?,
+                    },
+                ))
             })
-        } else {
-            None
-        },
-    );
-    
-    if args.baud != 1000000 {
-        siflitool.set_speed(args.baud).unwrap();
-    }
-    
-    let res = match args.command {
-        Some(Commands::WriteFlash(_)) => siflitool.write_flash(),
-        None => Ok(()),
+            .and_then(|(sector_size, block_size)| {
+                mkfs_image(&MkfsImageParams {
+                    source: mkfs.dir.clone(),
+                    output: mkfs.output.clone(),
+                    fs_type: mkfs.fs.into(),
+                    size: mkfs.size,
+                    sector_size,
+                    block_size,
+                    block_count: mkfs.block_count,
+                    label: mkfs.label.clone(),
+                })
+            });
+        match res {
+            Ok(info) => {
+                println!("{}", info);
+                println!("Image written to {}", mkfs.output);
+            }
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Commands::SignImage(ref sign)) = args.command {
+        let output = sign
+            .output
+            .clone()
+            .unwrap_or_else(|| sftool_lib::sign_image::signature_path(&sign.input));
+        match sign_image(&SignImageParams {
+            input: sign.input.clone(),
+            signature: output.clone(),
+            key_path: sign.key.clone(),
+        }) {
+            Ok(info) => {
+                println!("{}", info);
+                println!("Signature written to {}", output);
+            }
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.port.is_empty() {
+        eprintln!("Error: --port is required");
+        std::process::exit(1);
+    }
+
+    let mut ports: Vec<String> = Vec::new();
+    for pattern in args.port.iter() {
+        match expand_port_pattern(pattern) {
+            Ok(expanded) => {
+                for port in expanded {
+                    if !ports.contains(&port) {
+                        ports.push(port);
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let interactive = matches!(
+        args.command,
+        Some(Commands::Rtt(_)) | Some(Commands::Monitor(_)) | Some(Commands::GdbServer(_))
+    ) || matches!(args.after, AfterOperation::Rtt | AfterOperation::Monitor);
+    if interactive && ports.len() > 1 {
+        eprintln!("Error: RTT, the serial monitor and the GDB server can only be used with a single port");
+        std::process::exit(1);
+    }
+
+    let success = if ports.len() == 1 {
+        match run(&args, &ports[0], None, &|_| {}) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                false
+            }
+        }
+    } else {
+        run_parallel(&args, &ports)
     };
-    if let Err(e) = res {
-        eprintln!("Error: {:?}", e);
+
+    if !success {
+        std::process::exit(1);
     }
-    
-    if args.after != Operation::None {
-        siflitool.soft_reset().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_u32_decimal_and_hex() {
+        assert_eq!(parse_u32("4096"), Ok(4096));
+        assert_eq!(parse_u32("0x20000000"), Ok(0x2000_0000));
+        assert_eq!(parse_u32("0X2000_0000"), Ok(0x2000_0000));
+        assert_eq!(parse_u32("1_000"), Ok(1000));
+        assert_eq!(parse_u32("0xFFFFFFFF"), Ok(u32::MAX));
+        assert!(parse_u32("0x1_0000_0000").is_err());
+        assert!(parse_u32("0x").is_err());
+        assert!(parse_u32("-1").is_err());
+        assert!(parse_u32("12k").is_err());
     }
 }